@@ -0,0 +1,103 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Adapter from `Promise` to `std::future::Future`, enabled by the `futures-compat` feature.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use Promise;
+use private::{Event, EventDropper, GuardedEventHandle, PromiseNode};
+
+struct WakeState {
+    fired: bool,
+    waker: Option<Waker>,
+}
+
+struct WakeEvent {
+    state: Rc<RefCell<WakeState>>,
+}
+
+impl Event for WakeEvent {
+    fn fire(&mut self) {
+        let waker = {
+            let state = &mut *self.state.borrow_mut();
+            state.fired = true;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// A `Future` that resolves to the result of a `Promise`. Returned by `Promise::into_future()`.
+///
+/// The underlying promise still makes progress only as the gj event loop turns; polling
+/// just checks whether it has resolved yet, and the waker is woken from within the event loop
+/// once it has. The future must therefore be polled, and dropped, on the thread that owns the
+/// event loop, while that event loop is still alive.
+#[must_use]
+pub struct PromiseFuture<T, E>
+    where T: 'static,
+          E: 'static
+{
+    node: Option<Box<PromiseNode<T, E>>>,
+    state: Rc<RefCell<WakeState>>,
+    dropper: Option<EventDropper>,
+}
+
+impl<T, E> Promise<T, E> {
+    /// Converts this promise into a `std::future::Future`. Requires the `futures-compat` feature.
+    /// See [`PromiseFuture`](struct.PromiseFuture.html) for the constraints on polling it.
+    pub fn into_future(self) -> PromiseFuture<T, E> {
+        PromiseFuture {
+            node: Some(self.node),
+            state: Rc::new(RefCell::new(WakeState { fired: false, waker: None })),
+            dropper: None,
+        }
+    }
+}
+
+impl<T, E> Future for PromiseFuture<T, E> {
+    type Output = Result<T, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, E>> {
+        let this = self.get_mut();
+        if this.dropper.is_none() {
+            let (handle, dropper) = GuardedEventHandle::new();
+            this.node.as_mut().expect("polled after completion").on_ready(handle.clone());
+            handle.set(Box::new(WakeEvent { state: this.state.clone() }));
+            this.dropper = Some(dropper);
+        }
+
+        let state = &mut *this.state.borrow_mut();
+        if state.fired {
+            let node = this.node.take().expect("polled after completion");
+            Poll::Ready(node.get())
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
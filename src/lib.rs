@@ -48,7 +48,9 @@
 //! ```
 
 
+use std::any::Any;
 use std::cell::{Cell, RefCell};
+use std::iter::FromIterator;
 use std::rc::Rc;
 use std::result::Result;
 use private::{promise_node, BoolEvent, PromiseAndFulfillerHub, PromiseAndFulfillerWrapper,
@@ -70,11 +72,91 @@ macro_rules! pry {
     })
 }
 
+/// Waits for several promises with possibly different value types, resolving to a tuple of their
+/// values. If any of the promises fails, the rest are immediately cancelled.
+///
+/// `try_join!(a, b, c)` has type `Promise<(A, B, C), E>` when `a`, `b` and `c` have types
+/// `Promise<A, E>`, `Promise<B, E>` and `Promise<C, E>`. Between two and six promises are supported.
+#[macro_export]
+macro_rules! try_join {
+    ($a:expr, $b:expr $(,)*) => (
+        $crate::Promise::join($a, $b)
+    );
+    ($a:expr, $b:expr, $c:expr $(,)*) => (
+        $crate::Promise::join($crate::Promise::join($a, $b), $c)
+            .map(|((a, b), c)| ::std::result::Result::Ok((a, b, c)))
+    );
+    ($a:expr, $b:expr, $c:expr, $d:expr $(,)*) => (
+        $crate::Promise::join($crate::try_join!($a, $b, $c), $d)
+            .map(|((a, b, c), d)| ::std::result::Result::Ok((a, b, c, d)))
+    );
+    ($a:expr, $b:expr, $c:expr, $d:expr, $e:expr $(,)*) => (
+        $crate::Promise::join($crate::try_join!($a, $b, $c, $d), $e)
+            .map(|((a, b, c, d), e)| ::std::result::Result::Ok((a, b, c, d, e)))
+    );
+    ($a:expr, $b:expr, $c:expr, $d:expr, $e:expr, $f:expr $(,)*) => (
+        $crate::Promise::join($crate::try_join!($a, $b, $c, $d, $e), $f)
+            .map(|((a, b, c, d, e), f)| ::std::result::Result::Ok((a, b, c, d, e, f)))
+    );
+}
+
+/// Waits for whichever of several promises resolves first, possibly with different value types,
+/// and evaluates the matching arm. The remaining promises are cancelled.
+///
+/// Each arm has the form `pattern = promise => expression`. The promises must share an error type
+/// `E`, and the expressions must share a type `R`, in which case `race!` has type
+/// `Promise<R, E>`. If the first promise to resolve fails, the whole race fails with its error.
+///
+/// ```ignore
+/// let next = race! {
+///     bytes = read_chunk() => Event::Data(bytes),
+///     () = shutdown_signal() => Event::Shutdown,
+/// };
+/// ```
+#[macro_export]
+macro_rules! race {
+    ($pat:pat = $promise:expr => $body:expr $(,)*) => (
+        $crate::Promise::map($promise, move |$pat| ::std::result::Result::Ok($body))
+    );
+    ($pat:pat = $promise:expr => $body:expr, $($rest:tt)+) => (
+        $crate::Promise::exclusive_join($crate::race!($pat = $promise => $body),
+                                        $crate::race!($($rest)+))
+    );
+}
+
 mod private;
 mod handle_table;
 
+/// Re-exports the types and traits needed by most users of this crate, so that
+/// `use gj::prelude::*;` brings them all into scope.
+pub mod prelude {
+    pub use {ClosedEventPort, EventLoop, EventPort, ForkedPromise, FulfillerDropped, Loop,
+             ManualEventLoop, Priority, Promise, PromiseExt, PromiseFulfiller, TaskReaper, TaskSet,
+             WaitScope};
+
+    #[cfg(feature = "futures-compat")]
+    pub use PromiseFuture;
+}
+
+mod promise_ext;
+
+pub use promise_ext::PromiseExt;
+
+#[cfg(feature = "futures-compat")]
+mod future_compat;
+
+#[cfg(feature = "futures-compat")]
+pub use future_compat::PromiseFuture;
+
 /// A computation that might eventually resolve to a value of type `T` or to an error
 ///  of type `E`. Dropping the promise cancels the computation.
+///
+/// The core combinators are methods of `Promise` itself. Those built on top of them, such as
+/// `map_err()` and `zip_with()`, live in the [`PromiseExt`](trait.PromiseExt.html) trait.
+///
+/// Every combinator returns a plain `Promise<T, E>`, however it was built, so a promise can be
+/// stored in a struct field by name. To continue a stored promise later, keep it in an
+/// `Option<Promise<T, E>>` and `take()` it.
 #[must_use]
 pub struct Promise<T, E>
     where T: 'static,
@@ -122,6 +204,9 @@ impl<T, E> Promise<T, E> {
     ///
     /// Always returns immediately, even if the promise is already resolved. The earliest that
     /// `func` might be invoked is during the next `turn()` of the event loop.
+    ///
+    /// If `func` handles both branches synchronously and does not need to return a promise, use
+    /// `map_else()` instead.
     pub fn then_else<F, T1, E1>(self, func: F) -> Promise<T1, E1>
         where F: 'static,
               F: FnOnce(Result<T, E>) -> Promise<T1, E1>
@@ -158,6 +243,11 @@ impl<T, E> Promise<T, E> {
     }
 
     /// Calls `map_else()` with a default error handler that simply propagates all errors.
+    ///
+    /// `func` is called at most once, so it may move out of its captures. To apply the same
+    /// transformation to many promises, build a fresh `move` closure for each one, capturing
+    /// clones (or `Rc`s) of whatever state they share. If several branches need the mapped value,
+    /// use `PromiseExt::map_clone()`, which forks the result rather than mapping more than once.
     pub fn map<F, R>(self, func: F) -> Promise<R, E>
         where F: 'static,
               F: FnOnce(T) -> Result<R, E>,
@@ -171,40 +261,95 @@ impl<T, E> Promise<T, E> {
         })
     }
 
-    /// Transforms the error branch of the promise.
-    pub fn map_err<E1, F>(self, func: F) -> Promise<T, E1>
-        where F: 'static,
-              F: FnOnce(E) -> E1
-    {
-        self.map_else(|r| {
-            match r {
-                Ok(v) => Ok(v),
-                Err(e) => Err(func(e)),
-            }
-        })
-    }
-
-    /// Maps errors into a more general type.
-    pub fn lift<E1>(self) -> Promise<T, E1>
-        where E: Into<E1>
-    {
-        self.map_err(|e| e.into())
-    }
-
     /// Returns a new promise that resolves when either `self` or `other` resolves. The promise that
     /// doesn't resolve first is cancelled.
     pub fn exclusive_join(self, other: Promise<T, E>) -> Promise<T, E> {
         Promise { node: Box::new(private::promise_node::ExclusiveJoin::new(self.node, other.node)) }
     }
 
+    /// Returns a new promise that resolves to the values of both `self` and `other` once they have
+    /// both resolved. Unlike `all()`, the two promises may have different value types. If either
+    /// promise fails, the other is immediately cancelled. See also the `try_join!()` macro.
+    pub fn join<U>(self, other: Promise<U, E>) -> Promise<(T, U), E> {
+        Promise { node: Box::new(private::promise_node::Join::new(self.node, other.node)) }
+    }
+
     /// Transforms a collection of promises into a promise for a vector. If any of
     /// the promises fails, immediately cancels the remaining promises.
     pub fn all<I>(promises: I) -> Promise<Vec<T>, E>
         where I: Iterator<Item = Promise<T, E>>
+    {
+        Promise::collect_into(promises)
+    }
+
+    /// Like `all()`, but collects the values into any container that implements `FromIterator`,
+    /// such as a `HashSet`, or a `BTreeMap` when `T` is a key-value pair. The values are passed to
+    /// `FromIterator` in the order that the promises were given, not the order they resolved in.
+    pub fn collect_into<I, C>(promises: I) -> Promise<C, E>
+        where I: Iterator<Item = Promise<T, E>>,
+              C: 'static,
+              C: FromIterator<T>
     {
         Promise { node: Box::new(private::promise_node::ArrayJoin::new(promises)) }
     }
 
+    /// Calls `func` on each of `items` in turn, starting each call only after the promise
+    /// returned by the previous one has resolved, and collects the results in order. Unlike
+    /// `all()`, no two of the computations are ever in progress at the same time. If any of them
+    /// fails, the remaining items are skipped.
+    pub fn sequence<I, F>(items: I, func: F) -> Promise<Vec<T>, E>
+        where I: 'static,
+              I: Iterator,
+              F: 'static,
+              F: FnMut(I::Item) -> Promise<T, E>
+    {
+        fn step<T, E, I, F>(mut items: I, mut func: F, mut results: Vec<T>) -> Promise<Vec<T>, E>
+            where I: 'static,
+                  I: Iterator,
+                  F: 'static,
+                  F: FnMut(I::Item) -> Promise<T, E>
+        {
+            match items.next() {
+                None => Promise::ok(results),
+                Some(item) => {
+                    func(item).then(move |v| {
+                        results.push(v);
+                        step(items, func, results)
+                    })
+                }
+            }
+        }
+
+        Promise::ok(()).then(move |()| step(items, func, Vec::new()))
+    }
+
+    /// Returns a promise that resolves to the value of the first of `promises` to succeed, and
+    /// then cancels the rest. Errors are ignored unless every promise fails, in which case the
+    /// returned promise is rejected with all of the errors, in the order the promises were given.
+    pub fn race_ok<I>(promises: I) -> Promise<T, Vec<E>>
+        where I: Iterator<Item = Promise<T, E>>
+    {
+        Promise { node: Box::new(private::promise_node::RaceOk::new(promises)) }
+    }
+
+    /// Runs an asynchronous loop. Starting with `initial`, repeatedly calls `func` on the current
+    /// state until it yields `Loop::Break`, and resolves to the final state. Each iteration is
+    /// chained through the event loop, so the loop does not grow the stack no matter how many
+    /// times it runs. If any iteration fails, the loop stops and the returned promise is rejected.
+    pub fn repeat_until<F>(initial: T, mut func: F) -> Promise<T, E>
+        where F: 'static,
+              F: FnMut(T) -> Promise<Loop<T>, E>
+    {
+        Promise::ok(initial).then(move |state| {
+            func(state).then(move |step| {
+                match step {
+                    Loop::Continue(state) => Promise::repeat_until(state, func),
+                    Loop::Break(state) => Promise::ok(state),
+                }
+            })
+        })
+    }
+
     /// Forks the promise, so that multiple different clients can independently wait on the result.
     pub fn fork(self) -> ForkedPromise<T, E>
         where T: Clone,
@@ -223,6 +368,58 @@ impl<T, E> Promise<T, E> {
         })
     }
 
+    /// Calls `func` exactly once when the promise is finished with, whether it is fulfilled,
+    /// rejected, or dropped before resolving. This makes it suitable for releasing resources that
+    /// must not outlive the computation, even when the promise loses an `exclusive_join()`.
+    pub fn on_drop<F>(self, func: F) -> Promise<T, E>
+        where F: 'static,
+              F: FnOnce()
+    {
+        self.attach(private::OnDrop::new(func))
+    }
+
+    /// Catches any panic that occurs while computing this promise, including panics in callbacks
+    /// passed to `then()`, `map()`, and friends, and turns it into an `Err` in the success branch.
+    ///
+    /// A panic in such a callback does not unwind through the event loop. It is held in the
+    /// promises that depend on it, including joins such as `all()` and forks, and resumed when
+    /// their result is consumed, so without a `catch_unwind()` it still propagates out of
+    /// `wait()`. A task in a `TaskSet` has nowhere to hold a panic, so it is removed from its set
+    /// and the panic propagates out of whichever `wait()` is turning the event loop, which
+    /// remains usable afterwards.
+    ///
+    /// A held panic is never silently lost. If the promise holding it is dropped before its result
+    /// is consumed, for instance after `eagerly_evaluate()`, or because another branch of an
+    /// `all()` failed first, the panic resumes from the drop, or from the `wait()` that was
+    /// turning the event loop when the drop happened.
+    pub fn catch_unwind(self) -> Promise<Result<T, Box<Any + Send>>, E> {
+        Promise { node: Box::new(promise_node::CatchUnwind::new(self.node)) }
+    }
+
+    /// Sets the priority with which whatever is waiting on this promise gets scheduled once the
+    /// promise resolves. Whenever the event loop has both high-priority and normal events ready,
+    /// it runs the high-priority ones first, so that, for example, a flood of data-plane callbacks
+    /// cannot starve a liveness check. Promises have `Priority::Normal` by default.
+    pub fn priority(self, priority: Priority) -> Promise<T, E> {
+        Promise { node: Box::new(promise_node::Prioritized::new(self.node, priority)) }
+    }
+
+    /// Runs this promise in the background until it completes, even after the caller's callback has
+    /// returned, calling `error_handler` if it fails. The task belongs to the current event loop,
+    /// and is cancelled if it is still running when the event loop is dropped.
+    pub fn detach<F>(self, error_handler: F)
+        where F: 'static,
+              F: FnOnce(E)
+    {
+        let task = self.map_else(move |r| {
+            if let Err(e) = r {
+                error_handler(e);
+            }
+            Ok(())
+        });
+        with_current_event_loop(move |event_loop| event_loop.daemons.add(task.node));
+    }
+
     /// Forces eager evaluation of this promise. Use this if you are going to hold on to the promise
     /// for a while without consuming the result, but you want to make sure that the system actually
     /// processes it.
@@ -233,7 +430,8 @@ impl<T, E> Promise<T, E> {
     /// Runs the event loop until the promise is fulfilled.
     ///
     /// The `WaitScope` argument ensures that `wait()` can only be called at the top level of a
-    /// program. Waiting within event callbacks is disallowed.
+    /// program. Waiting within event callbacks is disallowed, and panics if attempted through a
+    /// `WaitScope` that escaped the top level, such as one borrowed from a `ManualEventLoop`.
     pub fn wait<E1>(mut self,
                     wait_scope: &WaitScope,
                     event_source: &mut EventPort<E1>)
@@ -242,6 +440,9 @@ impl<T, E> Promise<T, E> {
     {
         drop(wait_scope);
         with_current_event_loop(move |event_loop| {
+            if event_loop.currently_firing.get().is_some() {
+                panic!("cannot wait on a promise from within an event callback");
+            }
             let fired = ::std::rc::Rc::new(::std::cell::Cell::new(false));
             let done_event = BoolEvent::new(fired.clone());
             let (handle, _dropper) = private::GuardedEventHandle::new();
@@ -258,6 +459,101 @@ impl<T, E> Promise<T, E> {
             self.node.get()
         })
     }
+
+    /// Runs one pass of the event loop, without ever waiting on an event port. A pass runs the
+    /// events that are already queued, along with any continuations they trigger directly, but
+    /// not events queued breadth-first during the pass, such as the next step of a task that
+    /// yields with `yield_now()`. So a pass always ends, even if some task keeps rescheduling
+    /// itself, at either priority. Stops early if this promise resolves. Returns `Ok` with the result if the promise
+    /// resolved, and otherwise `Err` with the still-pending promise, which can be used as normal.
+    /// Useful in tests for asserting that a promise has not resolved yet. Like `wait()`, panics if
+    /// called from within an event callback.
+    pub fn poll_once(self, wait_scope: &WaitScope) -> Result<Result<T, E>, Promise<T, E>> {
+        drop(wait_scope);
+        with_current_event_loop(|event_loop| {
+            if event_loop.currently_firing.get().is_some() {
+                panic!("cannot poll a promise from within an event callback");
+            }
+        });
+        let polled = private::promise_node::Polled::new(self.node);
+
+        // Marks the end of the pass: any event queued breadth-first from now on runs after it.
+        let pass_done = Rc::new(Cell::new(false));
+        let (handle, _dropper) = private::GuardedEventHandle::new();
+        handle.set(Box::new(BoolEvent::new(pass_done.clone())));
+        handle.arm_breadth_first();
+
+        // High-priority events run before the marker no matter when they were queued, so they
+        // get a fence of their own, which never fires: the pass leaves alone whatever is queued
+        // behind it.
+        let (fence, _fence_dropper) = private::GuardedEventHandle::new();
+        fence.set(Box::new(BoolEvent::new(Rc::new(Cell::new(false)))));
+        fence.set_priority(Priority::High);
+        let high_fence = fence.event_handle();
+        fence.arm_breadth_first();
+
+        with_current_event_loop(|event_loop| {
+            while !polled.is_ready() && !pass_done.get() && event_loop.turn_fenced(high_fence) {}
+        });
+        if polled.is_ready() {
+            Ok(Box::new(polled).get())
+        } else {
+            Err(Promise { node: Box::new(polled) })
+        }
+    }
+}
+
+impl<T, E> From<T> for Promise<T, E> {
+    fn from(value: T) -> Promise<T, E> {
+        Promise::ok(value)
+    }
+}
+
+impl<E> Promise<(), E> {
+    /// Creates a promise that gives other tasks a chance to run. Anything chained onto it is
+    /// queued behind all events that are already ready, so a long computation can be split into
+    /// steps of the form `Promise::yield_now().then(|()| next_step())` without starving the rest
+    /// of the event loop.
+    pub fn yield_now() -> Promise<(), E> {
+        Promise::ok(())
+    }
+}
+
+impl<T> Promise<T, ::std::io::Error> {
+    /// Returns a promise that resolves to `on()` if this promise fails with an error of the given
+    /// kind, and otherwise resolves just like this promise. Useful for treating, say,
+    /// `ConnectionReset` as a normal end of stream.
+    pub fn catch_kind<F>(self, kind: ::std::io::ErrorKind, on: F) -> Promise<T, ::std::io::Error>
+        where F: 'static,
+              F: FnOnce() -> T
+    {
+        self.map_else(move |r| {
+            match r {
+                Err(ref e) if e.kind() == kind => Ok(on()),
+                r => r,
+            }
+        })
+    }
+}
+
+/// The result of one iteration of [`Promise::repeat_until()`](struct.Promise.html#method.repeat_until).
+pub enum Loop<S> {
+    /// Runs another iteration with the given state.
+    Continue(S),
+
+    /// Ends the loop with the given state.
+    Break(S),
+}
+
+/// The priority with which an event is scheduled. See
+/// [`Promise::priority()`](struct.Promise.html#method.priority).
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Priority {
+    /// The default.
+    Normal,
+
+    /// Runs ahead of all `Normal` events that are ready.
+    High,
 }
 
 /// A scope in which asynchronous programming can occur. Corresponds to the top level scope of some
@@ -265,8 +561,9 @@ impl<T, E> Promise<T, E> {
 /// the result of a promise.
 pub struct WaitScope(::std::marker::PhantomData<*mut u8>); // impl !Sync for WaitScope {}
 
-/// The result of `Promise::fork()`. Allows branches to be created. Dropping the `ForkedPromise`
-/// along with any branches created through `add_branch()` will cancel the computation.
+/// The result of `Promise::fork()` or `PromiseExt::map_clone()`. Allows branches to be created.
+/// Dropping the `ForkedPromise` along with any branches created through `add_branch()` will
+/// cancel the computation.
 pub struct ForkedPromise<T, E>
     where T: 'static + Clone,
           E: 'static + Clone
@@ -306,13 +603,17 @@ impl<E: Clone> EventPort<E> for ClosedEventPort<E> {
 }
 
 /// A queue of events being executed in a loop on a single thread.
+///
+/// Scheduling is deterministic. Ready events run in first-in, first-out order, except that
+/// continuations of an event that is currently firing run before the rest of the queue, and that
+/// `Priority::High` events run before `Priority::Normal` ones. No randomization is involved, so
+/// the same program run against the same inputs always completes its promises in the same order.
 pub struct EventLoop {
-    // daemons: TaskSetImpl,
+    daemons: private::TaskSetImpl<(), ()>,
     _last_runnable_state: bool,
     events: RefCell<handle_table::HandleTable<private::EventNode>>,
-    head: private::EventHandle,
-    tail: Cell<private::EventHandle>,
-    depth_first_insertion_point: Cell<private::EventHandle>,
+    normal_queue: private::EventQueue,
+    high_priority_queue: private::EventQueue,
     currently_firing: Cell<Option<private::EventHandle>>,
     to_destroy: Cell<Option<private::EventHandle>>,
 }
@@ -323,21 +624,39 @@ impl EventLoop {
     pub fn top_level<R, F>(main: F) -> R
         where F: FnOnce(&WaitScope) -> R
     {
+        let event_loop = EventLoop::create();
+        let result = main(event_loop.wait_scope());
+        drop(event_loop);
+        result
+    }
+
+    /// Creates an event loop for the current thread, panicking if one already exists. Runs the
+    /// promise returned by `main` to completion, waiting on `event_port` whenever no events are
+    /// queued, and then drops the event loop. Shorthand for calling `top_level()` and `wait()`.
+    pub fn block_on<F, T, E, E1>(event_port: &mut EventPort<E1>, main: F) -> Result<T, E>
+        where F: FnOnce(&WaitScope) -> Promise<T, E>,
+              T: 'static, E: 'static + From<E1>
+    {
+        EventLoop::top_level(move |wait_scope| main(wait_scope).wait(wait_scope, event_port))
+    }
+
+    /// Creates an event loop for the current thread, panicking if one already exists. Unlike
+    /// `top_level()`, this does not take over control flow: the caller drives the loop through
+    /// the returned [`ManualEventLoop`](struct.ManualEventLoop.html), which makes it possible to
+    /// step the loop from inside some other main loop. The event loop is dropped when the
+    /// `ManualEventLoop` is.
+    pub fn create() -> ManualEventLoop {
         let mut events = handle_table::HandleTable::<private::EventNode>::new();
-        let dummy = private::EventNode {
-            event: None,
-            next: None,
-            prev: None,
-        };
-        let head_handle = private::EventHandle(events.push(dummy));
+        let normal_queue = private::EventQueue::new(&mut events, Priority::Normal);
+        let high_priority_queue = private::EventQueue::new(&mut events, Priority::High);
 
         EVENT_LOOP.with(move |maybe_event_loop| {
             let event_loop = EventLoop {
                 _last_runnable_state: false,
                 events: RefCell::new(events),
-                head: head_handle,
-                tail: Cell::new(head_handle),
-                depth_first_insertion_point: Cell::new(head_handle), // insert after this node
+                normal_queue: normal_queue,
+                high_priority_queue: high_priority_queue,
+                daemons: private::TaskSetImpl::new(Box::new(private::DaemonReaper)),
                 currently_firing: Cell::new(None),
                 to_destroy: Cell::new(None),
             };
@@ -346,32 +665,19 @@ impl EventLoop {
             *maybe_event_loop.borrow_mut() = Some(event_loop);
         });
 
-        let wait_scope = WaitScope(::std::marker::PhantomData);
-        let result = main(&wait_scope);
-
-        EVENT_LOOP.with(move |maybe_event_loop| {
-            let el = ::std::mem::replace(&mut *maybe_event_loop.borrow_mut(), None);
-            match el {
-                None => unreachable!(),
-                Some(event_loop) => {
-                    // If there is still an event other than the head event, then there must have
-                    // been a memory leak.
-                    let remaining_events = event_loop.events.borrow().len();
-                    if remaining_events > 1 {
-                        ::std::mem::forget(event_loop); // Prevent double panic.
-                        panic!("{} leaked events found when cleaning up event loop. \
-                               Perhaps there is a reference cycle containing promises?",
-                               remaining_events - 1)
-                    }
-                }
-            }
-        });
+        ManualEventLoop { wait_scope: WaitScope(::std::marker::PhantomData) }
+    }
 
-        result
+    fn queue(&self, priority: Priority) -> &private::EventQueue {
+        match priority {
+            Priority::Normal => &self.normal_queue,
+            Priority::High => &self.high_priority_queue,
+        }
     }
 
     fn arm_depth_first(&self, event_handle: private::EventHandle) {
-        let insertion_node_next = self.events.borrow()[self.depth_first_insertion_point.get().0]
+        let queue = self.queue(self.events.borrow()[event_handle.0].priority);
+        let insertion_node_next = self.events.borrow()[queue.depth_first_insertion_point.get().0]
                                       .next;
 
         match insertion_node_next {
@@ -380,64 +686,159 @@ impl EventLoop {
                 self.events.borrow_mut()[event_handle.0].next = Some(next_handle);
             }
             None => {
-                self.tail.set(event_handle);
+                queue.tail.set(event_handle);
             }
         }
 
-        self.events.borrow_mut()[event_handle.0].prev = Some(self.depth_first_insertion_point
-                                                                 .get());
-        self.events.borrow_mut()[self.depth_first_insertion_point.get().0].next =
+        self.events.borrow_mut()[event_handle.0].prev = Some(queue.depth_first_insertion_point
+                                                                  .get());
+        self.events.borrow_mut()[queue.depth_first_insertion_point.get().0].next =
             Some(event_handle);
-        self.depth_first_insertion_point.set(event_handle);
+        queue.depth_first_insertion_point.set(event_handle);
     }
 
     fn arm_breadth_first(&self, event_handle: private::EventHandle) {
         let events = &mut *self.events.borrow_mut();
-        events[self.tail.get().0].next = Some(event_handle);
-        events[event_handle.0].prev = Some(self.tail.get());
-        self.tail.set(event_handle);
+        let queue = self.queue(events[event_handle.0].priority);
+        events[queue.tail.get().0].next = Some(event_handle);
+        events[event_handle.0].prev = Some(queue.tail.get());
+        queue.tail.set(event_handle);
     }
 
-    /// Runs the event loop for a single step.
+    /// Runs the event loop for a single step. High-priority events run before normal ones.
     fn turn(&self) -> bool {
+        let queue = if self.events.borrow()[self.high_priority_queue.head.0].next.is_some() {
+            &self.high_priority_queue
+        } else {
+            &self.normal_queue
+        };
+        self.fire_next(queue)
+    }
 
-        let event_handle = match self.events.borrow()[self.head.0].next {
+    /// Like `turn()`, but treats the high-priority queue as ending just before `high_fence`.
+    fn turn_fenced(&self, high_fence: private::EventHandle) -> bool {
+        let high_next = self.events.borrow()[self.high_priority_queue.head.0].next;
+        if high_next.is_some() && high_next != Some(high_fence) {
+            self.fire_next(&self.high_priority_queue)
+        } else {
+            self.fire_next(&self.normal_queue)
+        }
+    }
+
+    /// Fires the first event in `queue`. Returns `false` if the queue is empty.
+    fn fire_next(&self, queue: &private::EventQueue) -> bool {
+        let event_handle = match self.events.borrow()[queue.head.0].next {
             None => return false,
             Some(event_handle) => event_handle,
         };
-        self.depth_first_insertion_point.set(event_handle);
+        queue.depth_first_insertion_point.set(event_handle);
 
         self.currently_firing.set(Some(event_handle));
         let mut event = ::std::mem::replace(&mut self.events.borrow_mut()[event_handle.0].event,
                                             None)
                             .expect("No event to fire?");
-        event.fire();
+        // A panic that escapes an event must not leave the queue half-updated, so catch it here
+        // and resume it once the event has been unlinked.
+        let fired = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| event.fire()));
         self.currently_firing.set(None);
 
         let maybe_next = self.events.borrow()[event_handle.0].next;
-        self.events.borrow_mut()[self.head.0].next = maybe_next;
+        self.events.borrow_mut()[queue.head.0].next = maybe_next;
         if let Some(e) = maybe_next {
-            self.events.borrow_mut()[e.0].prev = Some(self.head);
+            self.events.borrow_mut()[e.0].prev = Some(queue.head);
         }
 
         self.events.borrow_mut()[event_handle.0].next = None;
         self.events.borrow_mut()[event_handle.0].prev = None;
 
-        if self.tail.get() == event_handle {
-            self.tail.set(self.head);
+        if queue.tail.get() == event_handle {
+            queue.tail.set(queue.head);
         }
 
-        self.depth_first_insertion_point.set(self.head);
+        queue.depth_first_insertion_point.set(queue.head);
 
         if let Some(event_handle) = self.to_destroy.get() {
             self.events.borrow_mut().remove(event_handle.0);
             self.to_destroy.set(None);
         }
 
+        if let Err(payload) = fired {
+            ::std::panic::resume_unwind(payload);
+        }
+
         true
     }
 }
 
+/// An event loop created by [`EventLoop::create()`](struct.EventLoop.html#method.create), to be
+/// driven manually by calling `turn()` or `run()`, or by waiting on promises through
+/// `wait_scope()`. Dropping it drops the event loop.
+pub struct ManualEventLoop {
+    wait_scope: WaitScope,
+}
+
+impl ManualEventLoop {
+    /// Gets the scope that can be used to `wait()` on promises in this event loop.
+    pub fn wait_scope(&self) -> &WaitScope {
+        &self.wait_scope
+    }
+
+    /// Runs a single queued event. Returns `false` if there were no events to run. Never blocks
+    /// waiting for external events; those are the responsibility of the caller.
+    pub fn turn(&self) -> bool {
+        with_current_event_loop(|event_loop| {
+            if event_loop.currently_firing.get().is_some() {
+                panic!("cannot turn the event loop from within an event callback");
+            }
+            event_loop.turn()
+        })
+    }
+
+    /// Runs queued events until either there are none left or `max_turns` events have run.
+    /// Returns the number of events that ran.
+    pub fn run(&self, max_turns: usize) -> usize {
+        let mut turns = 0;
+        while turns < max_turns && self.turn() {
+            turns += 1;
+        }
+        turns
+    }
+}
+
+impl Drop for ManualEventLoop {
+    fn drop(&mut self) {
+        // Detached tasks must be cancelled while the event loop is still installed. Cancelling one
+        // that holds a panic resumes the panic, which must wait until the loop is torn down.
+        let cleared = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+            with_current_event_loop(|event_loop| event_loop.daemons.clear())
+        }));
+
+        EVENT_LOOP.with(|maybe_event_loop| {
+            let el = ::std::mem::replace(&mut *maybe_event_loop.borrow_mut(), None);
+            match el {
+                None => unreachable!(),
+                Some(event_loop) => {
+                    // If there is still an event other than the queue head events, then there
+                    // must have been a memory leak.
+                    let remaining_events = event_loop.events.borrow().len();
+                    if remaining_events > 2 {
+                        ::std::mem::forget(event_loop); // Prevent double panic.
+                        if !::std::thread::panicking() && cleared.is_ok() {
+                            panic!("{} leaked events found when cleaning up event loop. \
+                                   Perhaps there is a reference cycle containing promises?",
+                                   remaining_events - 2)
+                        }
+                    }
+                }
+            }
+        });
+
+        if let Err(payload) = cleared {
+            ::std::panic::resume_unwind(payload);
+        }
+    }
+}
+
 /// Specifies an error to generate when a [`PromiseFulfiller`](struct.PromiseFulfiller.html) is
 /// dropped.
 pub trait FulfillerDropped {
@@ -23,8 +23,8 @@ use std::cell::{Cell, RefCell};
 use std::rc::{Rc, Weak};
 use std::collections::HashMap;
 use std::result::Result;
-use handle_table::Handle;
-use {EventLoop, TaskReaper};
+use handle_table::{Handle, HandleTable};
+use {EventLoop, Priority, TaskReaper};
 
 pub mod promise_node;
 
@@ -88,6 +88,7 @@ impl GuardedEventHandle {
                 event: None,
                 next: None,
                 prev: None,
+                priority: Priority::Normal,
             };
             let handle = EventHandle(event_loop.events.borrow_mut().push(node));
             let guarded_handle = GuardedEventHandle {
@@ -98,12 +99,31 @@ impl GuardedEventHandle {
         })
     }
 
+    pub fn event_handle(&self) -> EventHandle {
+        self.event_handle
+    }
+
     pub fn set(&self, event: Box<Event>) {
         with_current_event_loop(|event_loop| {
             event_loop.events.borrow_mut()[self.event_handle.0].event = Some(event);
         })
     }
 
+    pub fn set_priority(&self, priority: Priority) {
+        if self.still_valid.get() {
+            with_current_event_loop(|event_loop| {
+                let events = &mut *event_loop.events.borrow_mut();
+                let node = &mut events[self.event_handle.0];
+
+                // Changing the priority of an event that is already queued would leave it in the
+                // wrong queue.
+                if node.prev.is_none() {
+                    node.priority = priority;
+                }
+            });
+        }
+    }
+
     pub fn arm_breadth_first(self) {
         if self.still_valid.get() {
             with_current_event_loop(|event_loop| {
@@ -125,6 +145,31 @@ pub struct EventNode {
     pub event: Option<Box<Event>>,
     pub next: Option<EventHandle>,
     pub prev: Option<EventHandle>,
+    pub priority: Priority,
+}
+
+/// A linked list of armed events, threaded through the event loop's `EventNode`s.
+pub struct EventQueue {
+    pub head: EventHandle,
+    pub tail: Cell<EventHandle>,
+    pub depth_first_insertion_point: Cell<EventHandle>,
+}
+
+impl EventQueue {
+    pub fn new(events: &mut HandleTable<EventNode>, priority: Priority) -> EventQueue {
+        let dummy = EventNode {
+            event: None,
+            next: None,
+            prev: None,
+            priority: priority,
+        };
+        let head_handle = EventHandle(events.push(dummy));
+        EventQueue {
+            head: head_handle,
+            tail: Cell::new(head_handle),
+            depth_first_insertion_point: Cell::new(head_handle), // insert after this node
+        }
+    }
 }
 
 pub struct EventDropper {
@@ -155,14 +200,15 @@ impl Drop for EventDropper {
                     event_loop.events.borrow_mut()[e.0].next = event_node.next;
                 }
 
-                let insertion_point = event_loop.depth_first_insertion_point.get();
+                let queue = event_loop.queue(event_node.priority);
+                let insertion_point = queue.depth_first_insertion_point.get();
                 if insertion_point.0 == self_event_handle.0 {
-                    event_loop.depth_first_insertion_point.set(event_node.prev.unwrap());
+                    queue.depth_first_insertion_point.set(event_node.prev.unwrap());
                 }
 
-                let tail = event_loop.tail.get();
+                let tail = queue.tail.get();
                 if tail.0 == self_event_handle.0 {
-                    event_loop.tail.set(event_node.prev.unwrap());
+                    queue.tail.set(event_node.prev.unwrap());
                 }
             }
         });
@@ -185,6 +231,31 @@ impl Event for BoolEvent {
     }
 }
 
+/// Calls a function when dropped. Used to implement `Promise::on_drop()`.
+pub struct OnDrop<F>
+    where F: FnOnce()
+{
+    func: Option<F>,
+}
+
+impl<F> OnDrop<F>
+    where F: FnOnce()
+{
+    pub fn new(func: F) -> OnDrop<F> {
+        OnDrop { func: Some(func) }
+    }
+}
+
+impl<F> Drop for OnDrop<F>
+    where F: FnOnce()
+{
+    fn drop(&mut self) {
+        if let Some(func) = self.func.take() {
+            func();
+        }
+    }
+}
+
 pub enum OnReadyEvent {
     Empty,
     AlreadyReady,
@@ -317,6 +388,26 @@ impl<T, E> TaskSetImpl<T, E> {
         handle.set(Box::new(task));
         self.tasks.borrow_mut().insert(handle.event_handle, dropper);
     }
+
+    /// Cancels all tasks that have not yet finished, including any that get added while the
+    /// existing ones are being cancelled.
+    pub fn clear(&self) {
+        loop {
+            let tasks = ::std::mem::replace(&mut *self.tasks.borrow_mut(), HashMap::new());
+            if tasks.is_empty() {
+                break;
+            }
+            drop(tasks);
+        }
+    }
+}
+
+/// Reaper for the event loop's daemon tasks. `Promise::detach()` handles errors before a task is
+/// added, so there is nothing left to do here.
+pub struct DaemonReaper;
+
+impl TaskReaper<(), ()> for DaemonReaper {
+    fn task_failed(&mut self, _error: ()) {}
 }
 
 pub struct Task<T, E>
@@ -329,6 +420,14 @@ pub struct Task<T, E>
     event_handle: GuardedEventHandle,
 }
 
+impl<T, E> Task<T, E> {
+    fn remove_from_task_set(&self) {
+        if let Some(tasks) = self.weak_tasks.upgrade() {
+            tasks.borrow_mut().remove(&self.event_handle.event_handle);
+        }
+    }
+}
+
 impl<T, E> Event for Task<T, E> {
     fn fire(&mut self) {
         let maybe_node = ::std::mem::replace(&mut self.node, None);
@@ -338,20 +437,24 @@ impl<T, E> Event for Task<T, E> {
                 match self.weak_reaper.upgrade() {
                     None => (),
                     Some(reaper) => {
-                        match node.get() {
-                            Ok(v) => {
+                        match promise_node::get_catching(node) {
+                            Ok(Ok(v)) => {
                                 reaper.borrow_mut().task_succeeded(v);
                             }
-                            Err(e) => {
+                            Ok(Err(e)) => {
                                 reaper.borrow_mut().task_failed(e);
                             }
+                            Err(payload) => {
+                                // A reaper has no way to hear about a panic, so remove the task
+                                // and let the panic propagate out of whoever is turning the loop.
+                                self.remove_from_task_set();
+                                payload.resume();
+                            }
                         }
                     }
                 }
             }
         }
-        if let Some(tasks) = self.weak_tasks.upgrade() {
-            tasks.borrow_mut().remove(&self.event_handle.event_handle);
-        }
+        self.remove_from_task_set();
     }
 }
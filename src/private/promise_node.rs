@@ -21,10 +21,13 @@
 
 #![allow(dead_code)]
 
+use std::any::Any;
 use std::rc::{Rc, Weak};
 use std::cell::RefCell;
+use std::iter::FromIterator;
+use std::panic::{self, AssertUnwindSafe};
 use std::result::Result;
-use Promise;
+use {Priority, Promise};
 use private::{Event, EventDropper, GuardedEventHandle, OnReadyEvent, PromiseNode};
 
 
@@ -83,6 +86,112 @@ impl<T, E> PromiseNode<T, E> for Immediate<T, E> {
     }
 }
 
+/// A panic caught while computing a promise, held in the promise graph until someone asks for the
+/// result. A held panic that gets dropped without being resumed is resumed right away, so that it
+/// is not silently lost, unless the thread is already panicking.
+pub struct HeldPanic {
+    payload: Option<Box<Any + Send>>,
+}
+
+impl HeldPanic {
+    pub fn resume(mut self) -> ! {
+        panic::resume_unwind(self.payload.take().expect("no payload?"))
+    }
+}
+
+impl Drop for HeldPanic {
+    fn drop(&mut self) {
+        if let Some(payload) = self.payload.take() {
+            if !::std::thread::panicking() {
+                panic::resume_unwind(payload);
+            }
+        }
+    }
+}
+
+/// Calls `node.get()`, catching any panic so that it can be held in the promise graph rather than
+/// unwinding through the event loop.
+pub fn get_catching<T, E>(node: Box<PromiseNode<T, E>>) -> Result<Result<T, E>, HeldPanic> {
+    panic::catch_unwind(AssertUnwindSafe(move || node.get()))
+        .map_err(|payload| HeldPanic { payload: Some(payload) })
+}
+
+/// A promise whose computation panicked. Resumes the panic when its result is requested.
+pub struct Panicked<T, E> {
+    payload: HeldPanic,
+    phantom_data: ::std::marker::PhantomData<Result<T, E>>,
+}
+
+impl<T, E> Panicked<T, E> {
+    pub fn new(payload: HeldPanic) -> Panicked<T, E> {
+        Panicked {
+            payload: payload,
+            phantom_data: ::std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T, E> PromiseNode<T, E> for Panicked<T, E> {
+    fn on_ready(&mut self, event: GuardedEventHandle) {
+        event.arm_breadth_first();
+    }
+    fn get(self: Box<Self>) -> Result<T, E> {
+        self.payload.resume()
+    }
+}
+
+/// A PromiseNode that converts a panic in its dependency into a value (implements
+/// `catch_unwind()`).
+pub struct CatchUnwind<T, E> {
+    dependency: Box<PromiseNode<T, E>>,
+}
+
+impl<T, E> CatchUnwind<T, E> {
+    pub fn new(dependency: Box<PromiseNode<T, E>>) -> CatchUnwind<T, E> {
+        CatchUnwind { dependency: dependency }
+    }
+}
+
+impl<T, E> PromiseNode<Result<T, Box<Any + Send>>, E> for CatchUnwind<T, E> {
+    fn on_ready(&mut self, event: GuardedEventHandle) {
+        self.dependency.on_ready(event);
+    }
+    fn get(self: Box<Self>) -> Result<Result<T, Box<Any + Send>>, E> {
+        let dependency = self.dependency;
+        match panic::catch_unwind(AssertUnwindSafe(move || dependency.get())) {
+            Ok(Ok(v)) => Ok(Ok(v)),
+            Ok(Err(e)) => Err(e),
+            Err(payload) => Ok(Err(payload)),
+        }
+    }
+}
+
+/// A PromiseNode that schedules whoever waits on it with a given priority (implements
+/// `priority()`).
+pub struct Prioritized<T, E> {
+    dependency: Box<PromiseNode<T, E>>,
+    priority: Priority,
+}
+
+impl<T, E> Prioritized<T, E> {
+    pub fn new(dependency: Box<PromiseNode<T, E>>, priority: Priority) -> Prioritized<T, E> {
+        Prioritized {
+            dependency: dependency,
+            priority: priority,
+        }
+    }
+}
+
+impl<T, E> PromiseNode<T, E> for Prioritized<T, E> {
+    fn on_ready(&mut self, event: GuardedEventHandle) {
+        event.set_priority(self.priority);
+        self.dependency.on_ready(event);
+    }
+    fn get(self: Box<Self>) -> Result<T, E> {
+        self.dependency.get()
+    }
+}
+
 /// A promise that never resolves.
 pub struct NeverDone<T, E> {
     phantom_data: ::std::marker::PhantomData<Result<T, E>>,
@@ -126,8 +235,10 @@ impl<T, E> Event for ChainEvent<T, E> {
         let state = ::std::mem::replace(&mut *self.state.borrow_mut(), ChainState::Step3);
         match state {
             ChainState::Step1(inner, on_ready_event, self_ptr) => {
-                match inner.get() {
-                    Ok(mut intermediate) => {
+                // A panic in the chained callback is held until someone asks for the result, so
+                // that it does not unwind through the event loop.
+                match get_catching(inner) {
+                    Ok(Ok(mut intermediate)) => {
                         match on_ready_event {
                             Some(event) => {
                                 intermediate.node.on_ready(event);
@@ -137,7 +248,7 @@ impl<T, E> Event for ChainEvent<T, E> {
 
                         *self.state.borrow_mut() = ChainState::Step2(intermediate.node, self_ptr);
                     }
-                    Err(e) => {
+                    Ok(Err(e)) => {
                         let mut node = Immediate::new(Err(e));
                         match on_ready_event {
                             Some(event) => {
@@ -146,6 +257,17 @@ impl<T, E> Event for ChainEvent<T, E> {
                             None => {}
                         }
 
+                        *self.state.borrow_mut() = ChainState::Step2(Box::new(node), self_ptr);
+                    }
+                    Err(payload) => {
+                        let mut node = Panicked::new(payload);
+                        match on_ready_event {
+                            Some(event) => {
+                                node.on_ready(event);
+                            }
+                            None => {}
+                        }
+
                         *self.state.borrow_mut() = ChainState::Step2(Box::new(node), self_ptr);
                     }
                 }
@@ -257,6 +379,7 @@ impl<T, E> Event for ArrayJoinBranch<T, E> {
         match stage {
             ArrayJoinStage::Uninit => unreachable!(),
             ArrayJoinStage::Cancelled(_) => unreachable!(),
+            ArrayJoinStage::Panicked(_) => unreachable!(),
             ArrayJoinStage::Active(mut branches) => {
                 let branch_stage = ::std::mem::replace(&mut branches[self.index],
                                                        ArrayBranchStage::Uninit);
@@ -264,8 +387,8 @@ impl<T, E> Event for ArrayJoinBranch<T, E> {
                     ArrayBranchStage::Uninit => unreachable!(),
                     ArrayBranchStage::Done(_) => unreachable!(),
                     ArrayBranchStage::Waiting(p, _) => {
-                        match p.get() {
-                            Ok(v) => {
+                        match get_catching(p) {
+                            Ok(Ok(v)) => {
                                 branches[self.index] = ArrayBranchStage::Done(v);
                                 state.count_left -= 1;
                                 if state.count_left == 0 {
@@ -273,10 +396,14 @@ impl<T, E> Event for ArrayJoinBranch<T, E> {
                                 }
                                 state.stage = ArrayJoinStage::Active(branches);
                             }
-                            Err(e) => {
+                            Ok(Err(e)) => {
                                 state.stage = ArrayJoinStage::Cancelled(e);
                                 state.on_ready_event.arm();
                             }
+                            Err(payload) => {
+                                state.stage = ArrayJoinStage::Panicked(payload);
+                                state.on_ready_event.arm();
+                            }
                         }
                     }
                 }
@@ -301,6 +428,7 @@ enum ArrayJoinStage<T, E>
     Uninit,
     Active(Vec<ArrayBranchStage<T, E>>),
     Cancelled(E),
+    Panicked(HeldPanic),
 }
 
 struct ArrayJoinState<T, E>
@@ -312,15 +440,18 @@ struct ArrayJoinState<T, E>
     stage: ArrayJoinStage<T, E>,
 }
 
-pub struct ArrayJoin<T, E>
+/// Promise node that waits for all of its branches and collects their values into a `C`
+/// (implements `all()` and `collect_into()`).
+pub struct ArrayJoin<T, E, C>
     where T: 'static,
           E: 'static
 {
     state: Rc<RefCell<ArrayJoinState<T, E>>>,
+    phantom_data: ::std::marker::PhantomData<C>,
 }
 
-impl<T, E> ArrayJoin<T, E> {
-    pub fn new<I>(promises: I) -> ArrayJoin<T, E>
+impl<T, E, C> ArrayJoin<T, E, C> {
+    pub fn new<I>(promises: I) -> ArrayJoin<T, E, C>
         where I: Iterator<Item = Promise<T, E>>
     {
         let state = Rc::new(RefCell::new(ArrayJoinState {
@@ -348,29 +479,169 @@ impl<T, E> ArrayJoin<T, E> {
         }
         state.borrow_mut().count_left = branches.len();
         state.borrow_mut().stage = ArrayJoinStage::Active(branches);
-        ArrayJoin { state: state }
+        ArrayJoin {
+            state: state,
+            phantom_data: ::std::marker::PhantomData,
+        }
     }
 }
 
-impl<T, E> PromiseNode<Vec<T>, E> for ArrayJoin<T, E> {
+impl<T, E, C> PromiseNode<C, E> for ArrayJoin<T, E, C>
+    where C: FromIterator<T>
+{
     fn on_ready(&mut self, event: GuardedEventHandle) {
         self.state.borrow_mut().on_ready_event.init(event);
     }
-    fn get(self: Box<Self>) -> Result<Vec<T>, E> {
+    fn get(self: Box<Self>) -> Result<C, E> {
         let stage = ::std::mem::replace(&mut self.state.borrow_mut().stage, ArrayJoinStage::Uninit);
         match stage {
             ArrayJoinStage::Uninit => unreachable!(),
             ArrayJoinStage::Cancelled(e) => Err(e),
+            ArrayJoinStage::Panicked(payload) => payload.resume(),
             ArrayJoinStage::Active(branches) => {
-                let mut result = Vec::new();
+                Ok(branches.into_iter()
+                           .map(|branch| {
+                               match branch {
+                                   ArrayBranchStage::Uninit |
+                                   ArrayBranchStage::Waiting(..) => unreachable!(),
+                                   ArrayBranchStage::Done(v) => v,
+                               }
+                           })
+                           .collect())
+            }
+        }
+    }
+}
+
+struct RaceOkBranch<T, E>
+    where T: 'static,
+          E: 'static
+{
+    index: usize,
+    state: Weak<RefCell<RaceOkState<T, E>>>,
+}
+
+impl<T, E> Event for RaceOkBranch<T, E> {
+    fn fire(&mut self) {
+        let strong_state = self.state.upgrade().expect("dangling pointer?");
+        let state = &mut *strong_state.borrow_mut();
+        let branch_stage = ::std::mem::replace(&mut state.branches[self.index],
+                                               RaceOkBranchStage::Uninit);
+        match branch_stage {
+            RaceOkBranchStage::Uninit => unreachable!(),
+            RaceOkBranchStage::Failed(_) => unreachable!(),
+            RaceOkBranchStage::Waiting(p, _) => {
+                match get_catching(p) {
+                    Ok(Ok(v)) => {
+                        state.winner = Some(v);
+
+                        // Cancel the losers.
+                        state.branches = Vec::new();
+                        state.on_ready_event.arm();
+                    }
+                    Ok(Err(e)) => {
+                        state.branches[self.index] = RaceOkBranchStage::Failed(e);
+                        state.count_left -= 1;
+                        if state.count_left == 0 {
+                            state.on_ready_event.arm();
+                        }
+                    }
+                    Err(payload) => {
+                        state.panic = Some(payload);
+                        state.branches = Vec::new();
+                        state.on_ready_event.arm();
+                    }
+                }
+            }
+        }
+    }
+}
+
+enum RaceOkBranchStage<T, E>
+    where T: 'static,
+          E: 'static
+{
+    Uninit,
+    Waiting(Box<PromiseNode<T, E>>, EventDropper),
+    Failed(E),
+}
+
+struct RaceOkState<T, E>
+    where T: 'static,
+          E: 'static
+{
+    count_left: usize,
+    on_ready_event: OnReadyEvent,
+    branches: Vec<RaceOkBranchStage<T, E>>,
+    winner: Option<T>,
+    panic: Option<HeldPanic>,
+}
+
+/// Promise node that resolves to the first of its branches to succeed (implements `race_ok()`).
+pub struct RaceOk<T, E>
+    where T: 'static,
+          E: 'static
+{
+    state: Rc<RefCell<RaceOkState<T, E>>>,
+}
+
+impl<T, E> RaceOk<T, E> {
+    pub fn new<I>(promises: I) -> RaceOk<T, E>
+        where I: Iterator<Item = Promise<T, E>>
+    {
+        let state = Rc::new(RefCell::new(RaceOkState {
+            count_left: 0,
+            on_ready_event: OnReadyEvent::Empty,
+            branches: Vec::new(),
+            winner: None,
+            panic: None,
+        }));
+        let mut idx = 0;
+        let branches: Vec<RaceOkBranchStage<T, E>> =
+            promises.into_iter()
+                    .map(|promise| {
+                        let mut node = promise.node;
+                        let (handle, dropper) = GuardedEventHandle::new();
+                        node.on_ready(handle.clone());
+                        handle.set(Box::new(RaceOkBranch {
+                            index: idx,
+                            state: Rc::downgrade(&state),
+                        }));
+                        idx += 1;
+                        RaceOkBranchStage::Waiting(node, dropper)
+                    })
+                    .collect();
+        if branches.len() == 0 {
+            state.borrow_mut().on_ready_event.arm();
+        }
+        state.borrow_mut().count_left = branches.len();
+        state.borrow_mut().branches = branches;
+        RaceOk { state: state }
+    }
+}
+
+impl<T, E> PromiseNode<T, Vec<E>> for RaceOk<T, E> {
+    fn on_ready(&mut self, event: GuardedEventHandle) {
+        self.state.borrow_mut().on_ready_event.init(event);
+    }
+    fn get(self: Box<Self>) -> Result<T, Vec<E>> {
+        let state = &mut *self.state.borrow_mut();
+        if let Some(payload) = state.panic.take() {
+            payload.resume();
+        }
+        match state.winner.take() {
+            Some(v) => Ok(v),
+            None => {
+                let branches = ::std::mem::replace(&mut state.branches, Vec::new());
+                let mut errors = Vec::new();
                 for branch in branches {
                     match branch {
-                        ArrayBranchStage::Uninit |
-                        ArrayBranchStage::Waiting(..) => unreachable!(),
-                        ArrayBranchStage::Done(v) => result.push(v),
+                        RaceOkBranchStage::Uninit |
+                        RaceOkBranchStage::Waiting(..) => unreachable!(),
+                        RaceOkBranchStage::Failed(e) => errors.push(e),
                     }
                 }
-                Ok(result)
+                Err(errors)
             }
         }
     }
@@ -467,6 +738,205 @@ impl<T, E> PromiseNode<T, E> for ExclusiveJoin<T, E> {
     }
 }
 
+struct PolledEvent {
+    on_ready_event: Rc<RefCell<OnReadyEvent>>,
+}
+
+impl Event for PolledEvent {
+    fn fire(&mut self) {
+        self.on_ready_event.borrow_mut().arm();
+    }
+}
+
+/// Promise node that has already registered with its dependency, so that readiness can be checked
+/// without consuming it (implements `poll_once()`).
+pub struct Polled<T, E>
+    where T: 'static,
+          E: 'static
+{
+    node: Box<PromiseNode<T, E>>,
+    on_ready_event: Rc<RefCell<OnReadyEvent>>,
+    _dropper: EventDropper,
+}
+
+impl<T, E> Polled<T, E> {
+    pub fn new(mut node: Box<PromiseNode<T, E>>) -> Polled<T, E> {
+        let on_ready_event = Rc::new(RefCell::new(OnReadyEvent::Empty));
+        let (handle, dropper) = GuardedEventHandle::new();
+        node.on_ready(handle.clone());
+        handle.set(Box::new(PolledEvent { on_ready_event: on_ready_event.clone() }));
+        Polled {
+            node: node,
+            on_ready_event: on_ready_event,
+            _dropper: dropper,
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.on_ready_event.borrow().is_already_ready()
+    }
+}
+
+impl<T, E> PromiseNode<T, E> for Polled<T, E> {
+    fn on_ready(&mut self, event: GuardedEventHandle) {
+        self.on_ready_event.borrow_mut().init(event);
+    }
+    fn get(self: Box<Self>) -> Result<T, E> {
+        self.node.get()
+    }
+}
+
+enum JoinBranchStage<T, E>
+    where T: 'static,
+          E: 'static
+{
+    Uninit,
+    Waiting(Box<PromiseNode<T, E>>, EventDropper),
+    Done(T),
+}
+
+impl<T, E> JoinBranchStage<T, E> {
+    fn is_done(&self) -> bool {
+        match self {
+            &JoinBranchStage::Done(_) => true,
+            _ => false,
+        }
+    }
+}
+
+struct JoinBranch<T, U, E>
+    where T: 'static,
+          U: 'static,
+          E: 'static
+{
+    state: Weak<RefCell<JoinState<T, U, E>>>,
+    side: ExclusiveJoinSide,
+}
+
+impl<T, U, E> Event for JoinBranch<T, U, E> {
+    fn fire(&mut self) {
+        let strong_state = self.state.upgrade().expect("dangling pointer?");
+        let state = &mut *strong_state.borrow_mut();
+        let result = match self.side {
+            ExclusiveJoinSide::Left => {
+                match ::std::mem::replace(&mut state.left, JoinBranchStage::Uninit) {
+                    JoinBranchStage::Waiting(node, _dropper) => {
+                        get_catching(node).map(|r| r.map(|v| state.left = JoinBranchStage::Done(v)))
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            ExclusiveJoinSide::Right => {
+                match ::std::mem::replace(&mut state.right, JoinBranchStage::Uninit) {
+                    JoinBranchStage::Waiting(node, _dropper) => {
+                        get_catching(node).map(|r| r.map(|v| state.right = JoinBranchStage::Done(v)))
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        };
+        match result {
+            Ok(Ok(())) => {
+                if state.left.is_done() && state.right.is_done() {
+                    state.on_ready_event.arm();
+                }
+            }
+            Ok(Err(e)) => {
+                // Cancel the other side.
+                state.left = JoinBranchStage::Uninit;
+                state.right = JoinBranchStage::Uninit;
+                state.error = Some(e);
+                state.on_ready_event.arm();
+            }
+            Err(payload) => {
+                state.left = JoinBranchStage::Uninit;
+                state.right = JoinBranchStage::Uninit;
+                state.panic = Some(payload);
+                state.on_ready_event.arm();
+            }
+        }
+    }
+}
+
+struct JoinState<T, U, E>
+    where T: 'static,
+          U: 'static,
+          E: 'static
+{
+    on_ready_event: OnReadyEvent,
+    left: JoinBranchStage<T, E>,
+    right: JoinBranchStage<U, E>,
+    error: Option<E>,
+    panic: Option<HeldPanic>,
+}
+
+/// Promise node that waits for two branches with possibly different value types and resolves
+/// to a pair of their values (implements `join()`).
+pub struct Join<T, U, E>
+    where T: 'static,
+          U: 'static,
+          E: 'static
+{
+    state: Rc<RefCell<JoinState<T, U, E>>>,
+}
+
+impl<T, U, E> Join<T, U, E> {
+    pub fn new(mut left: Box<PromiseNode<T, E>>,
+               mut right: Box<PromiseNode<U, E>>)
+               -> Join<T, U, E> {
+        let state = Rc::new(RefCell::new(JoinState {
+            on_ready_event: OnReadyEvent::Empty,
+            left: JoinBranchStage::Uninit,
+            right: JoinBranchStage::Uninit,
+            error: None,
+            panic: None,
+        }));
+
+        {
+            let (handle, dropper) = GuardedEventHandle::new();
+            left.on_ready(handle.clone());
+            handle.set(Box::new(JoinBranch {
+                state: Rc::downgrade(&state),
+                side: ExclusiveJoinSide::Left,
+            }));
+            state.borrow_mut().left = JoinBranchStage::Waiting(left, dropper);
+        }
+
+        {
+            let (handle, dropper) = GuardedEventHandle::new();
+            right.on_ready(handle.clone());
+            handle.set(Box::new(JoinBranch {
+                state: Rc::downgrade(&state),
+                side: ExclusiveJoinSide::Right,
+            }));
+            state.borrow_mut().right = JoinBranchStage::Waiting(right, dropper);
+        }
+
+        Join { state: state }
+    }
+}
+
+impl<T, U, E> PromiseNode<(T, U), E> for Join<T, U, E> {
+    fn on_ready(&mut self, event: GuardedEventHandle) {
+        self.state.borrow_mut().on_ready_event.init(event);
+    }
+    fn get(self: Box<Self>) -> Result<(T, U), E> {
+        let state = &mut *self.state.borrow_mut();
+        if let Some(payload) = state.panic.take() {
+            payload.resume();
+        }
+        if let Some(e) = state.error.take() {
+            return Err(e);
+        }
+        let left = ::std::mem::replace(&mut state.left, JoinBranchStage::Uninit);
+        let right = ::std::mem::replace(&mut state.right, JoinBranchStage::Uninit);
+        match (left, right) {
+            (JoinBranchStage::Done(a), JoinBranchStage::Done(b)) => Ok((a, b)),
+            _ => unreachable!(),
+        }
+    }
+}
+
 enum ForkHubStage<T, E>
     where T: 'static + Clone,
           E: 'static + Clone
@@ -474,6 +944,7 @@ enum ForkHubStage<T, E>
     Uninitialized,
     Waiting(Box<PromiseNode<T, E>>),
     Done(Result<T, E>),
+    Panicked(Option<HeldPanic>),
 }
 
 struct ForkHubState<T, E>
@@ -529,7 +1000,11 @@ impl<T, E> ForkHub<T, E>
             ()
         }
 
-        let on_ready_event = Rc::new(RefCell::new(OnReadyEvent::Empty));
+        let already_ready = match hub.borrow().state.borrow().stage {
+            ForkHubStage::Panicked(_) => OnReadyEvent::AlreadyReady,
+            _ => OnReadyEvent::Empty,
+        };
+        let on_ready_event = Rc::new(RefCell::new(already_ready));
         {
             let state = &mut hub.borrow_mut().state;
             state.borrow_mut().branches.push(Rc::downgrade(&on_ready_event));
@@ -558,7 +1033,7 @@ impl<T, E> Event for ForkEvent<T, E>
         let stage = ::std::mem::replace(&mut self.state.borrow_mut().stage,
                                         ForkHubStage::Uninitialized);
         let result = match stage {
-            ForkHubStage::Waiting(inner) => inner.get(),
+            ForkHubStage::Waiting(inner) => get_catching(inner),
             _ => unreachable!(),
         };
 
@@ -570,7 +1045,10 @@ impl<T, E> Event for ForkEvent<T, E>
                 }
             }
         }
-        self.state.borrow_mut().stage = ForkHubStage::Done(result);
+        self.state.borrow_mut().stage = match result {
+            Ok(r) => ForkHubStage::Done(r),
+            Err(payload) => ForkHubStage::Panicked(Some(payload)),
+        };
     }
 }
 
@@ -591,9 +1069,17 @@ impl<T, E> PromiseNode<T, E> for ForkBranch<T, E>
     }
     fn get(self: Box<Self>) -> Result<T, E> {
         let state = &self.hub.borrow().state;
-        let result = match state.borrow().stage {
+        let result = match state.borrow_mut().stage {
             ForkHubStage::Done(Ok(ref v)) => Ok(v.clone()),
             ForkHubStage::Done(Err(ref e)) => Err(e.clone()),
+            // The first branch to ask resumes the original panic. The payload can't be cloned, so
+            // later branches just panic with a message.
+            ForkHubStage::Panicked(ref mut payload) => {
+                match payload.take() {
+                    Some(payload) => payload.resume(),
+                    None => panic!("forked promise panicked"),
+                }
+            }
             _ => unreachable!(),
         };
         result
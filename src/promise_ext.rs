@@ -0,0 +1,189 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Combinators that are built entirely out of the core methods of `Promise`.
+
+use {ForkedPromise, Promise};
+
+/// Combinators for promises, built out of the core methods of `Promise` such as `then()`,
+/// `map_else()`, and `join()`. Bring them into scope with `use gj::PromiseExt;`, or with
+/// `use gj::prelude::*;`.
+///
+/// Grouped by what they do:
+///
+///  * chaining: `then_into()`, `and()`, `and_discard()`, `then_spawn()`;
+///  * errors: `map_err()`, `lift()`;
+///  * values: `drop_value()`, `zip_with()`, `map_clone()`.
+pub trait PromiseExt<T, E>: Sized
+    where T: 'static,
+          E: 'static
+{
+    /// Like `then()`, but `func` may return either a promise or a plain value. A plain value is
+    /// converted through the `From<T> for Promise<T, E>` impl, as if by `Promise::ok()`. When
+    /// `func` returns a promise, the type of the resulting promise usually needs to be spelled
+    /// out, because a `Promise<T1, E>` could itself be the value of a `Promise<Promise<T1, E>, E>`.
+    fn then_into<F, T1, R>(self, func: F) -> Promise<T1, E>
+        where F: 'static,
+              F: FnOnce(T) -> R,
+              R: Into<Promise<T1, E>>,
+              T1: 'static;
+
+    /// Waits for `self`, discards its value, and then calls `next` and waits for the promise it
+    /// returns. `next` is only called once `self` resolves, so the work it starts is deferred
+    /// until then; if `self` is rejected, `next` is never called.
+    fn and<F, T1>(self, next: F) -> Promise<T1, E>
+        where F: 'static,
+              F: FnOnce() -> Promise<T1, E>,
+              T1: 'static;
+
+    /// Like `and()`, but resolves to the value of `self` once the promise returned by `next` also
+    /// succeeds.
+    fn and_discard<F, T1>(self, next: F) -> Promise<T, E>
+        where F: 'static,
+              F: FnOnce() -> Promise<T1, E>,
+              T1: 'static;
+
+    /// Returns a promise that resolves to the same value as `self`. Once `self` succeeds, also
+    /// starts the promise returned by `func` as a detached task, as if by `detach(error_handler)`,
+    /// without waiting for it to finish.
+    fn then_spawn<F, G, E1>(self, func: F, error_handler: G) -> Promise<T, E>
+        where F: 'static,
+              F: FnOnce(&T) -> Promise<(), E1>,
+              G: 'static,
+              G: FnOnce(E1),
+              E1: 'static;
+
+    /// Transforms the error branch of the promise.
+    fn map_err<E1, F>(self, func: F) -> Promise<T, E1>
+        where F: 'static,
+              F: FnOnce(E) -> E1,
+              E1: 'static;
+
+    /// Maps errors into a more general type.
+    fn lift<E1>(self) -> Promise<T, E1>
+        where E: Into<E1>,
+              E1: 'static;
+
+    /// Discards the value of the promise, keeping only its success or failure. Together with
+    /// `lift()`, this gives promises with different value types a common type, so that a
+    /// collection of side-effecting tasks can be passed to `Promise::all()`.
+    fn drop_value(self) -> Promise<(), E>;
+
+    /// Like `join()`, but combines the two values with `func` instead of resolving to a pair.
+    fn zip_with<U, R, F>(self, other: Promise<U, E>, func: F) -> Promise<R, E>
+        where F: 'static,
+              F: FnOnce(T, U) -> R,
+              U: 'static,
+              R: 'static;
+
+    /// Like `map()`, but forks the result, so that several branches can each get a clone of the
+    /// mapped value while `func` still runs only once. Shorthand for `map(func).fork()`.
+    fn map_clone<F, R>(self, func: F) -> ForkedPromise<R, E>
+        where F: 'static,
+              F: FnOnce(T) -> Result<R, E>,
+              R: 'static + Clone,
+              E: Clone;
+}
+
+impl<T, E> PromiseExt<T, E> for Promise<T, E>
+    where T: 'static,
+          E: 'static
+{
+    fn then_into<F, T1, R>(self, func: F) -> Promise<T1, E>
+        where F: 'static,
+              F: FnOnce(T) -> R,
+              R: Into<Promise<T1, E>>,
+              T1: 'static
+    {
+        self.then(move |v| func(v).into())
+    }
+
+    fn and<F, T1>(self, next: F) -> Promise<T1, E>
+        where F: 'static,
+              F: FnOnce() -> Promise<T1, E>,
+              T1: 'static
+    {
+        self.then(move |_| next())
+    }
+
+    fn and_discard<F, T1>(self, next: F) -> Promise<T, E>
+        where F: 'static,
+              F: FnOnce() -> Promise<T1, E>,
+              T1: 'static
+    {
+        self.then(move |v| next().map(move |_| Ok(v)))
+    }
+
+    fn then_spawn<F, G, E1>(self, func: F, error_handler: G) -> Promise<T, E>
+        where F: 'static,
+              F: FnOnce(&T) -> Promise<(), E1>,
+              G: 'static,
+              G: FnOnce(E1),
+              E1: 'static
+    {
+        self.map(move |v| {
+            func(&v).detach(error_handler);
+            Ok(v)
+        })
+    }
+
+    fn map_err<E1, F>(self, func: F) -> Promise<T, E1>
+        where F: 'static,
+              F: FnOnce(E) -> E1,
+              E1: 'static
+    {
+        self.map_else(|r| {
+            match r {
+                Ok(v) => Ok(v),
+                Err(e) => Err(func(e)),
+            }
+        })
+    }
+
+    fn lift<E1>(self) -> Promise<T, E1>
+        where E: Into<E1>,
+              E1: 'static
+    {
+        self.map_err(|e| e.into())
+    }
+
+    fn drop_value(self) -> Promise<(), E> {
+        self.map(|_| Ok(()))
+    }
+
+    fn zip_with<U, R, F>(self, other: Promise<U, E>, func: F) -> Promise<R, E>
+        where F: 'static,
+              F: FnOnce(T, U) -> R,
+              U: 'static,
+              R: 'static
+    {
+        self.join(other).map(move |(a, b)| Ok(func(a, b)))
+    }
+
+    fn map_clone<F, R>(self, func: F) -> ForkedPromise<R, E>
+        where F: 'static,
+              F: FnOnce(T) -> Result<R, E>,
+              R: 'static + Clone,
+              E: Clone
+    {
+        self.map(func).fork()
+    }
+}
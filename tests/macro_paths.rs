@@ -0,0 +1,84 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+// Invokes the exported macros by path, without `#[macro_use]`, to check that they don't depend
+// on being imported by name.
+
+extern crate gj;
+
+use gj::{EventLoop, Promise, ClosedEventPort};
+
+#[test]
+fn try_join_by_path() {
+    EventLoop::top_level(|wait_scope| -> Result<(), ()> {
+        let tuple = try!(gj::try_join!(Promise::ok(1), Promise::ok("two"), Promise::ok(3),
+                                       Promise::ok(4), Promise::ok(5), Promise::ok(6))
+                             .wait(wait_scope, &mut ClosedEventPort(())));
+        assert_eq!(tuple, (1, "two", 3, 4, 5, 6));
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn race_by_path() {
+    EventLoop::top_level(|wait_scope| -> Result<(), ()> {
+        let winner = gj::race! {
+            a = Promise::<u32, ()>::never_done() => a,
+            b = Promise::ok(2u32) => b * 10,
+            c = Promise::<u32, ()>::never_done() => c,
+        };
+        assert_eq!(try!(winner.wait(wait_scope, &mut ClosedEventPort(()))), 20);
+        Ok(())
+    }).unwrap();
+}
+
+// Shadows `Ok`, to check that the macros don't pick up whatever `Ok` is in scope where they are
+// invoked.
+mod shadowed_ok {
+    use gj::{EventLoop, Promise, ClosedEventPort};
+
+    #[allow(non_snake_case, dead_code)]
+    fn Ok<T>(value: T) -> T {
+        value
+    }
+
+    #[test]
+    fn try_join() {
+        EventLoop::top_level(|wait_scope| -> Result<(), ()> {
+            let tuple = try!(gj::try_join!(Promise::ok(1), Promise::ok(2), Promise::ok(3))
+                                 .wait(wait_scope, &mut ClosedEventPort(())));
+            assert_eq!(tuple, (1, 2, 3));
+            ::std::result::Result::Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn race() {
+        EventLoop::top_level(|wait_scope| -> Result<(), ()> {
+            let winner = gj::race! {
+                a = Promise::<u32, ()>::never_done() => a,
+                b = Promise::ok(2u32) => b * 10,
+            };
+            assert_eq!(try!(winner.wait(wait_scope, &mut ClosedEventPort(()))), 20);
+            ::std::result::Result::Ok(())
+        }).unwrap();
+    }
+}
@@ -721,3 +721,971 @@ fn eagerly_evaluate() {
         Ok(())
     }).unwrap();
 }
+
+#[test]
+fn drop_value() {
+    use gj::PromiseExt;
+
+    EventLoop::top_level(|wait_scope| -> Result<(),()> {
+        let mut event_port = ClosedEventPort(());
+        let tasks: Vec<Promise<(), ()>> =
+            vec![Promise::<u32, ()>::ok(123).drop_value(),
+                 Promise::<&'static str, ()>::ok("abc").drop_value(),
+                 Promise::<Vec<u8>, ()>::ok(vec![1, 2, 3]).drop_value()];
+        let result = try!(Promise::all(tasks.into_iter()).wait(wait_scope, &mut event_port));
+        assert_eq!(result.len(), 3);
+
+        let failed: Promise<(), ()> = Promise::<u32, ()>::err(()).drop_value();
+        assert_eq!(failed.wait(wait_scope, &mut event_port), Err(()));
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn catch_unwind() {
+    EventLoop::top_level(|wait_scope| -> Result<(),()> {
+        let mut event_port = ClosedEventPort(());
+
+        let promise: Promise<u32, ()> = Promise::ok(()).then(|()| -> Promise<u32, ()> {
+            panic!("callback panicked")
+        });
+        let result = try!(promise.catch_unwind().wait(wait_scope, &mut event_port));
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&'static str>(), Some(&"callback panicked"));
+
+        let promise: Promise<u32, ()> = Promise::ok(()).map(|()| Ok(7)).then(|x| Promise::ok(x + 1));
+        let result = try!(promise.catch_unwind().wait(wait_scope, &mut event_port));
+        assert_eq!(result.ok(), Some(8));
+
+        let promise: Promise<u32, ()> = Promise::ok(()).then(|()| Promise::err(()));
+        assert!(promise.catch_unwind().wait(wait_scope, &mut event_port).is_err());
+
+        // The event loop is still usable.
+        let value = try!(Promise::<u32, ()>::ok(1).then(|x| Promise::ok(x + 1))
+                             .wait(wait_scope, &mut event_port));
+        assert_eq!(value, 2);
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn catch_unwind_in_joins() {
+    fn panicking() -> Promise<u32, ()> {
+        Promise::ok(()).then(|()| Promise::ok(1)).map(|_| -> Result<u32, ()> {
+            panic!("map panicked")
+        })
+    }
+
+    EventLoop::top_level(|wait_scope| -> Result<(),()> {
+        let mut event_port = ClosedEventPort(());
+
+        let all = Promise::all(vec![Promise::never_done(), panicking()].into_iter());
+        let payload = try!(all.catch_unwind().wait(wait_scope, &mut event_port)).unwrap_err();
+        assert_eq!(payload.downcast_ref::<&'static str>(), Some(&"map panicked"));
+
+        let joined = Promise::<(), ()>::never_done().join(panicking());
+        assert!(try!(joined.catch_unwind().wait(wait_scope, &mut event_port)).is_err());
+
+        let raced = Promise::race_ok(vec![Promise::never_done(), panicking()].into_iter());
+        assert!(raced.catch_unwind().wait(wait_scope, &mut ClosedEventPort(Vec::new()))
+                     .unwrap().is_err());
+
+        let mut forked = panicking().fork();
+        let branch1 = forked.add_branch();
+        let branch2 = forked.add_branch();
+        let payload = try!(branch1.catch_unwind().wait(wait_scope, &mut event_port)).unwrap_err();
+        assert_eq!(payload.downcast_ref::<&'static str>(), Some(&"map panicked"));
+        assert!(try!(branch2.catch_unwind().wait(wait_scope, &mut event_port)).is_err());
+        assert!(try!(forked.add_branch().catch_unwind().wait(wait_scope, &mut event_port)).is_err());
+
+        // The event loop is still usable.
+        let value = try!(Promise::<u32, ()>::ok(1).then(|x| Promise::ok(x + 1))
+                             .wait(wait_scope, &mut event_port));
+        assert_eq!(value, 2);
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn task_panic_leaves_loop_usable() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    struct Reaper;
+    impl gj::TaskReaper<u32, ()> for Reaper {
+        fn task_failed(&mut self, _error: ()) {}
+    }
+
+    EventLoop::top_level(|wait_scope| -> Result<(),()> {
+        let mut tasks = gj::TaskSet::new(Box::new(Reaper));
+        tasks.add(Promise::ok(()).then(|()| Promise::ok(1)).map(|_| -> Result<u32, ()> {
+            panic!("task panicked")
+        }));
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            Promise::<(), ()>::never_done().wait(wait_scope, &mut ClosedEventPort(()))
+        }));
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&'static str>(), Some(&"task panicked"));
+
+        let value = try!(Promise::<u32, ()>::ok(1).then(|x| Promise::ok(x + 1))
+                             .wait(wait_scope, &mut ClosedEventPort(())));
+        assert_eq!(value, 2);
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn panic_propagates_from_wait() {
+    let result = ::std::panic::catch_unwind(|| {
+        EventLoop::top_level(|wait_scope| -> Result<(),()> {
+            let promise: Promise<(), ()> = Promise::ok(()).then(|()| -> Promise<(), ()> {
+                panic!("callback panicked")
+            });
+            promise.wait(wait_scope, &mut ClosedEventPort(()))
+        })
+    });
+    assert!(result.is_err());
+}
+
+#[test]
+fn yield_now() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    fn busy_loop(id: u32, n: u32, log: Rc<RefCell<Vec<u32>>>) -> Promise<(), ()> {
+        log.borrow_mut().push(id);
+        if n == 0 {
+            Promise::ok(())
+        } else {
+            Promise::yield_now().then(move |()| busy_loop(id, n - 1, log))
+        }
+    }
+
+    EventLoop::top_level(|wait_scope| -> Result<(),()> {
+        let mut event_port = ClosedEventPort(());
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let log1 = log.clone();
+        let log2 = log.clone();
+        let promise1 = Promise::ok(()).then(move |()| busy_loop(1, 2, log1));
+        let promise2 = Promise::ok(()).then(move |()| busy_loop(2, 2, log2));
+        try!(Promise::all(vec![promise1, promise2].into_iter()).wait(wait_scope, &mut event_port));
+        assert_eq!(*log.borrow(), vec![1, 2, 1, 2, 1, 2]);
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn on_drop() {
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+    EventLoop::top_level(|wait_scope| -> Result<(),()> {
+        let mut event_port = ClosedEventPort(());
+        let count = Rc::new(Cell::new(0u32));
+
+        let count1 = count.clone();
+        let promise: Promise<u32, ()> = Promise::ok(()).then(|()| Promise::ok(5))
+            .on_drop(move || count1.set(count1.get() + 1));
+        assert_eq!(try!(promise.wait(wait_scope, &mut event_port)), 5);
+        assert_eq!(count.get(), 1);
+
+        let count2 = count.clone();
+        let promise: Promise<u32, ()> = Promise::ok(()).then(|()| Promise::err(()))
+            .on_drop(move || count2.set(count2.get() + 1));
+        assert_eq!(promise.wait(wait_scope, &mut event_port), Err(()));
+        assert_eq!(count.get(), 2);
+
+        let count3 = count.clone();
+        let (promise, _fulfiller) = Promise::<u32, ()>::and_fulfiller();
+        let promise = promise.on_drop(move || count3.set(count3.get() + 1));
+        let winner = Promise::ok(()).then(|()| Promise::ok(6));
+        assert_eq!(try!(promise.exclusive_join(winner).wait(wait_scope, &mut event_port)), 6);
+        assert_eq!(count.get(), 3);
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn repeat_until() {
+    use gj::Loop;
+
+    EventLoop::top_level(|wait_scope| -> Result<(),()> {
+        let mut event_port = ClosedEventPort(());
+        let mut steps = 0;
+        let promise = Promise::repeat_until(1u64, move |n| {
+            steps += 1;
+            assert!(steps <= 11);
+            if n >= 1000 {
+                Promise::ok(Loop::Break(n))
+            } else {
+                Promise::ok(()).map(move |()| Ok(Loop::Continue(n * 2)))
+            }
+        });
+        assert_eq!(try!(promise.wait(wait_scope, &mut event_port)), 1024);
+
+        let promise = Promise::repeat_until(0u32, |n| {
+            if n == 3 {
+                Promise::err(())
+            } else {
+                Promise::ok(Loop::Continue(n + 1))
+            }
+        });
+        assert_eq!(promise.wait(wait_scope, &mut event_port), Err(()));
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn repeat_until_million() {
+    // Every iteration resolves immediately. The continuations must be trampolined through the
+    // event loop rather than nested on the stack.
+    use gj::Loop;
+
+    EventLoop::top_level(|wait_scope| -> Result<(),()> {
+        let mut event_port = ClosedEventPort(());
+        let promise = Promise::repeat_until(0u32, |n| {
+            if n == 1000000 {
+                Promise::ok(Loop::Break(n))
+            } else {
+                Promise::ok(Loop::Continue(n + 1))
+            }
+        });
+        assert_eq!(try!(promise.wait(wait_scope, &mut event_port)), 1000000);
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn and() {
+    use std::rc::Rc;
+    use std::cell::Cell;
+    use gj::PromiseExt;
+
+    EventLoop::top_level(|wait_scope| -> Result<(),()> {
+        let mut event_port = ClosedEventPort(());
+
+        let promise = Promise::<u32, ()>::ok(1).and(|| Promise::ok("second"));
+        assert_eq!(try!(promise.wait(wait_scope, &mut event_port)), "second");
+
+        let promise = Promise::<u32, ()>::ok(1).and_discard(|| Promise::ok("second"));
+        assert_eq!(try!(promise.wait(wait_scope, &mut event_port)), 1);
+
+        let promise = Promise::<u32, ()>::ok(1).and_discard(|| Promise::<(), ()>::err(()));
+        assert_eq!(promise.wait(wait_scope, &mut event_port), Err(()));
+
+        // `next` is not called until `self` resolves.
+        let started = Rc::new(Cell::new(false));
+        let started1 = started.clone();
+        let (first, fulfiller) = Promise::<(), ()>::and_fulfiller();
+        let promise = first.and(move || {
+            started1.set(true);
+            Promise::ok(2)
+        });
+        let promise = match promise.poll_once(wait_scope) {
+            Ok(_) => panic!("promise should still be pending"),
+            Err(promise) => promise,
+        };
+        assert!(!started.get());
+        fulfiller.fulfill(());
+        assert_eq!(try!(promise.wait(wait_scope, &mut event_port)), 2);
+        assert!(started.get());
+
+        let promise = Promise::<(), ()>::err(()).and(|| -> Promise<u32, ()> {
+            panic!("next should not have been called")
+        });
+        assert_eq!(promise.wait(wait_scope, &mut event_port), Err(()));
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn race_ok() {
+    EventLoop::top_level(|wait_scope| -> Result<(),()> {
+        let mut event_port = ClosedEventPort(vec![]);
+
+        let slow = Promise::<u32, u32>::never_done().map(|_| -> Result<u32, u32> { panic!("should have been cancelled") });
+        let promises = vec![Promise::ok(()).then(|()| Promise::err(1)),
+                            slow,
+                            Promise::ok(()).then(|()| Promise::ok(123))];
+        let result = Promise::race_ok(promises.into_iter()).wait(wait_scope, &mut event_port);
+        assert_eq!(result, Ok(123));
+
+        let promises: Vec<Promise<u32, u32>> =
+            vec![Promise::ok(()).then(|()| Promise::ok(()).then(|()| Promise::err(1))),
+                 Promise::err(2),
+                 Promise::ok(()).then(|()| Promise::err(3))];
+        let result = Promise::race_ok(promises.into_iter()).wait(wait_scope, &mut event_port);
+        assert_eq!(result, Err(vec![1, 2, 3]));
+
+        let promises: Vec<Promise<u32, u32>> = Vec::new();
+        let result = Promise::race_ok(promises.into_iter()).wait(wait_scope, &mut event_port);
+        assert_eq!(result, Err(vec![]));
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn manual_event_loop() {
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+    let event_loop = EventLoop::create();
+    let done = Rc::new(Cell::new(false));
+    let done1 = done.clone();
+    let promise: Promise<(), ()> = Promise::ok(()).then(|()| Promise::ok(())).map(move |()| {
+        done1.set(true);
+        Ok(())
+    }).eagerly_evaluate();
+
+    assert!(event_loop.run(100) > 0);
+    assert!(done.get());
+    assert!(!event_loop.turn());
+    assert_eq!(promise.wait(event_loop.wait_scope(), &mut ClosedEventPort(())), Ok(()));
+    drop(event_loop);
+
+    // The event loop has been torn down, so we can create another one.
+    EventLoop::top_level(|wait_scope| -> Result<(),()> {
+        Promise::ok(()).wait(wait_scope, &mut ClosedEventPort(()))
+    }).unwrap();
+}
+
+#[test]
+fn wait_from_within_callback() {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::rc::Rc;
+
+    let event_loop = Rc::new(EventLoop::create());
+    let event_loop1 = event_loop.clone();
+    let promise = Promise::<(), ()>::ok(()).then(move |()| {
+        let inner = Promise::<(), ()>::ok(());
+        Promise::ok(pry!(inner.wait(event_loop1.wait_scope(), &mut ClosedEventPort(()))))
+    });
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        promise.wait(event_loop.wait_scope(), &mut ClosedEventPort(()))
+    }));
+    let payload = result.unwrap_err();
+    assert_eq!(payload.downcast_ref::<&'static str>(),
+               Some(&"cannot wait on a promise from within an event callback"));
+
+    let event_loop1 = event_loop.clone();
+    let promise = Promise::<(), ()>::ok(()).then(move |()| {
+        match Promise::<(), ()>::ok(()).poll_once(event_loop1.wait_scope()) {
+            Ok(result) => Promise::ok(pry!(result)),
+            Err(promise) => promise,
+        }
+    });
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        promise.wait(event_loop.wait_scope(), &mut ClosedEventPort(()))
+    }));
+    let payload = result.unwrap_err();
+    assert_eq!(payload.downcast_ref::<&'static str>(),
+               Some(&"cannot poll a promise from within an event callback"));
+
+    // The event loop is still usable.
+    assert_eq!(Promise::<u32, ()>::ok(1).then(|x| Promise::ok(x + 1))
+                   .wait(event_loop.wait_scope(), &mut ClosedEventPort(())),
+               Ok(2));
+}
+
+#[test]
+fn sequence() {
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+    EventLoop::top_level(|wait_scope| -> Result<(),()> {
+        let mut event_port = ClosedEventPort(());
+        let in_flight = Rc::new(Cell::new(false));
+        let promise = Promise::sequence(vec![1u32, 2, 3].into_iter(), move |x| {
+            assert!(!in_flight.get());
+            in_flight.set(true);
+            let in_flight1 = in_flight.clone();
+            Promise::yield_now().then(move |()| {
+                in_flight1.set(false);
+                Promise::ok(x * 10)
+            })
+        });
+        assert_eq!(try!(promise.wait(wait_scope, &mut event_port)), vec![10, 20, 30]);
+
+        let promise = Promise::sequence(vec![1u32, 2, 3].into_iter(), |x| {
+            assert!(x < 3, "should have stopped after the failure");
+            if x == 2 { Promise::err(()) } else { Promise::ok(x) }
+        });
+        assert_eq!(promise.wait(wait_scope, &mut event_port), Err(()));
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn collect_into() {
+    use std::collections::{BTreeMap, HashSet};
+
+    EventLoop::top_level(|wait_scope| -> Result<(),()> {
+        let mut event_port = ClosedEventPort(());
+        let promises: Vec<Promise<u32, ()>> =
+            vec![Promise::ok(1), Promise::ok(()).then(|()| Promise::ok(2)), Promise::ok(1)];
+        let set: HashSet<u32> =
+            try!(Promise::collect_into(promises.into_iter()).wait(wait_scope, &mut event_port));
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&1) && set.contains(&2));
+
+        let (second, fulfiller) = Promise::<(&'static str, u32), ()>::and_fulfiller();
+        let promises = vec![Promise::ok(("a", 1)), second, Promise::ok(("a", 3))];
+        fulfiller.fulfill(("b", 2));
+        let map: BTreeMap<&'static str, u32> =
+            try!(Promise::collect_into(promises.into_iter()).wait(wait_scope, &mut event_port));
+        assert_eq!(map.get("a"), Some(&3));
+        assert_eq!(map.get("b"), Some(&2));
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn priority() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use gj::Priority;
+
+    EventLoop::top_level(|wait_scope| -> Result<(),()> {
+        let mut event_port = ClosedEventPort(());
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        let mut promises: Vec<Promise<(), ()>> = Vec::new();
+        for i in 0..3 {
+            let log = log.clone();
+            promises.push(Promise::ok(()).then(move |()| {
+                log.borrow_mut().push(i);
+                Promise::ok(())
+            }));
+        }
+
+        let (promise, fulfiller) = Promise::<(), ()>::and_fulfiller();
+        let log1 = log.clone();
+        promises.push(promise.priority(Priority::High).then(move |()| {
+            log1.borrow_mut().push(100);
+            Promise::ok(())
+        }));
+        let log2 = log.clone();
+        promises.push(Promise::ok(()).priority(Priority::High).then(move |()| {
+            log2.borrow_mut().push(99);
+            fulfiller.fulfill(());
+            Promise::ok(())
+        }));
+
+        try!(Promise::all(promises.into_iter()).wait(wait_scope, &mut event_port));
+        assert_eq!(*log.borrow(), vec![99, 100, 0, 1, 2]);
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn then_into() {
+    use gj::PromiseExt;
+
+    EventLoop::top_level(|wait_scope| -> Result<(),()> {
+        let mut event_port = ClosedEventPort(());
+
+        let promise = Promise::<u32, ()>::ok(1).then_into(|x| x + 1);
+        assert_eq!(try!(promise.wait(wait_scope, &mut event_port)), 2);
+
+        let promise: Promise<u32, ()> = Promise::ok(1).then_into(|x| {
+            Promise::ok(()).map(move |()| Ok(x + 2))
+        });
+        assert_eq!(try!(promise.wait(wait_scope, &mut event_port)), 3);
+
+        let promise: Promise<u32, ()> = Promise::ok(4).then_into(|x| {
+            if x == 4 { Promise::err(()) } else { x.into() }
+        });
+        assert_eq!(promise.wait(wait_scope, &mut event_port), Err(()));
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn block_on() {
+    let result = EventLoop::block_on(&mut ClosedEventPort(()), |_wait_scope| {
+        Promise::<u32, ()>::ok(1).then(|x| Promise::ok(x + 1))
+    });
+    assert_eq!(result, Ok(2));
+
+    let result = EventLoop::block_on(&mut ClosedEventPort(()), |_wait_scope| {
+        let (promise, _fulfiller) = Promise::<u32, ()>::and_fulfiller();
+        promise
+    });
+    assert_eq!(result, Err(()));
+}
+
+#[test]
+fn join() {
+    use std::rc::Rc;
+    use std::cell::Cell;
+    EventLoop::top_level(|wait_scope| -> Result<(), ()> {
+        let (left, left_fulfiller) = Promise::<u32, ()>::and_fulfiller();
+        let right = Promise::ok("right").then(|s| Promise::ok(s.len()));
+        let joined = left.join(right);
+        left_fulfiller.fulfill(7);
+        assert_eq!(try!(joined.wait(wait_scope, &mut ClosedEventPort(()))), (7, 5));
+
+        let dropped = Rc::new(Cell::new(false));
+        let dropped1 = dropped.clone();
+        let pending = Promise::<u32, ()>::never_done().on_drop(move || dropped1.set(true));
+        let joined = pending.join(Promise::<(), ()>::err(()));
+        assert_eq!(joined.wait(wait_scope, &mut ClosedEventPort(())), Err(()));
+        assert!(dropped.get());
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn try_join() {
+    EventLoop::top_level(|wait_scope| -> Result<(), ()> {
+        let (a, b, c) = try!(try_join!(Promise::ok(1u8),
+                                       Promise::ok("two"),
+                                       Promise::ok(3.0f64)).wait(wait_scope, &mut ClosedEventPort(())));
+        assert_eq!((a, b, c), (1, "two", 3.0));
+
+        let tuple = try!(try_join!(Promise::ok(1), Promise::ok(2), Promise::ok(3),
+                                   Promise::ok(4), Promise::ok(5), Promise::ok(6),)
+                             .wait(wait_scope, &mut ClosedEventPort(())));
+        assert_eq!(tuple, (1, 2, 3, 4, 5, 6));
+
+        let failed = try_join!(Promise::ok(1u32), Promise::<u32, ()>::err(()),
+                               Promise::<u32, ()>::never_done());
+        assert_eq!(failed.wait(wait_scope, &mut ClosedEventPort(())), Err(()));
+        Ok(())
+    }).unwrap();
+}
+
+#[cfg(feature = "futures-compat")]
+#[test]
+fn into_future() {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::{Context, Poll, Wake, Waker};
+
+    struct FlagWaker(AtomicBool);
+    impl Wake for FlagWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    let event_loop = EventLoop::create();
+    let flag = Arc::new(FlagWaker(AtomicBool::new(false)));
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    let (promise, fulfiller) = Promise::<u32, ()>::and_fulfiller();
+    let mut future = promise.map(|v| Ok(v + 1)).into_future();
+    assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Pending);
+    assert!(!flag.0.load(Ordering::SeqCst));
+
+    fulfiller.fulfill(41);
+    event_loop.run(100);
+    assert!(flag.0.load(Ordering::SeqCst));
+    assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Ready(Ok(42)));
+}
+
+#[test]
+fn race() {
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+    #[derive(Debug, PartialEq)]
+    enum Winner {
+        Number(u32),
+        Text(usize),
+        Unit,
+    }
+
+    EventLoop::top_level(|wait_scope| -> Result<(), ()> {
+        let dropped = Rc::new(Cell::new(0));
+        let dropped1 = dropped.clone();
+        let dropped2 = dropped.clone();
+        let (unit, _unit_fulfiller) = Promise::<(), ()>::and_fulfiller();
+
+        let winner = race! {
+            n = Promise::<u32, ()>::never_done().on_drop(move || dropped1.set(dropped1.get() + 1))
+                => Winner::Number(n),
+            s = Promise::ok("hello").then(|s| Promise::ok(s)) => Winner::Text(s.len()),
+            () = unit.on_drop(move || dropped2.set(dropped2.get() + 1)) => Winner::Unit,
+        };
+        assert_eq!(try!(winner.wait(wait_scope, &mut ClosedEventPort(()))), Winner::Text(5));
+        assert_eq!(dropped.get(), 2);
+
+        let failed = race! {
+            x = Promise::<u32, u32>::never_done() => x,
+            y = Promise::<u32, u32>::err(7) => y + 1,
+        };
+        assert_eq!(failed.wait(wait_scope, &mut ClosedEventPort(0u32)), Err(7));
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn detach_during_teardown() {
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+    let inner_cancelled = Rc::new(Cell::new(false));
+    let inner_cancelled1 = inner_cancelled.clone();
+
+    EventLoop::top_level(move |_wait_scope| {
+        // Cancelling this task at teardown detaches another one, which must be cancelled too.
+        Promise::<(), ()>::never_done().on_drop(move || {
+            Promise::<(), ()>::never_done().on_drop(move || inner_cancelled1.set(true))
+                                           .detach(|()| ());
+        }).detach(|()| ());
+    });
+
+    assert!(inner_cancelled.get());
+}
+
+#[test]
+fn detach() {
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+    let done = Rc::new(Cell::new(false));
+    let failed = Rc::new(Cell::new(None));
+    let cancelled = Rc::new(Cell::new(false));
+    let done1 = done.clone();
+    let failed1 = failed.clone();
+    let cancelled1 = cancelled.clone();
+
+    EventLoop::top_level(move |wait_scope| -> Result<(), ()> {
+        let handler = Promise::<(), ()>::ok(()).then(move |()| {
+            // Detached tasks outlive the callback that spawned them.
+            Promise::<(), ()>::ok(()).then(|()| Promise::ok(())).map(move |()| {
+                done1.set(true);
+                Ok(())
+            }).detach(|()| unreachable!());
+            Promise::<(), u32>::err(3).detach(move |e| failed1.set(Some(e)));
+            Promise::<(), ()>::never_done().on_drop(move || cancelled1.set(true)).detach(|()| ());
+            Promise::ok(())
+        });
+        try!(handler.wait(wait_scope, &mut ClosedEventPort(())));
+        try!(Promise::<(), ()>::yield_now().wait(wait_scope, &mut ClosedEventPort(())));
+        Ok(())
+    }).unwrap();
+
+    assert!(done.get());
+    assert_eq!(failed.get(), Some(3));
+    assert!(cancelled.get());
+}
+
+#[test]
+fn prelude() {
+    use gj::prelude::*;
+
+    struct Reaper;
+    impl TaskReaper<(), ()> for Reaper {
+        fn task_failed(&mut self, _error: ()) {}
+    }
+
+    EventLoop::top_level(|wait_scope: &WaitScope| -> Result<(), ()> {
+        let mut tasks: TaskSet<(), ()> = TaskSet::new(Box::new(Reaper));
+        tasks.add(Promise::ok(()));
+        let (promise, fulfiller): (Promise<u32, ()>, PromiseFulfiller<u32, ()>) =
+            Promise::and_fulfiller();
+        fulfiller.fulfill(1);
+        assert_eq!(try!(promise.map_err(|()| ()).wait(wait_scope, &mut ClosedEventPort(()))), 1);
+        Ok(())
+    }).unwrap();
+
+    let event_loop: ManualEventLoop = EventLoop::create();
+    assert!(!event_loop.turn());
+    #[cfg(feature = "futures-compat")]
+    let _future: PromiseFuture<(), ()> = Promise::ok(()).into_future();
+}
+
+#[test]
+fn zip_with() {
+    use gj::PromiseExt;
+
+    EventLoop::top_level(|wait_scope| -> Result<(), ()> {
+        let header = Promise::ok("Content-Length: ");
+        let body = Promise::ok(()).then(|()| Promise::ok(vec![0u8; 12]));
+        let message = header.zip_with(body, |h, b| format!("{}{}", h, b.len()));
+        assert_eq!(try!(message.wait(wait_scope, &mut ClosedEventPort(()))), "Content-Length: 12");
+
+        let failed = Promise::<u32, ()>::never_done().zip_with(Promise::<u32, ()>::err(()),
+                                                               |a, b| a + b);
+        assert_eq!(failed.wait(wait_scope, &mut ClosedEventPort(())), Err(()));
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn poll_once_with_perpetual_task() {
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+    fn tick(count: Rc<Cell<u32>>) -> Promise<(), ()> {
+        count.set(count.get() + 1);
+        Promise::yield_now().then(move |()| tick(count))
+    }
+
+    EventLoop::top_level(|wait_scope| -> Result<(), ()> {
+        let ticks = Rc::new(Cell::new(0));
+        let ticks1 = ticks.clone();
+        Promise::ok(()).then(move |()| tick(ticks1)).detach(|()| ());
+
+        let (promise, fulfiller) = Promise::<u32, ()>::and_fulfiller();
+        let promise = match promise.poll_once(wait_scope) {
+            Ok(_) => panic!("promise should still be pending"),
+            Err(promise) => promise,
+        };
+        assert_eq!(ticks.get(), 1);
+
+        let promise = match promise.poll_once(wait_scope) {
+            Ok(_) => panic!("promise should still be pending"),
+            Err(promise) => promise,
+        };
+        assert_eq!(ticks.get(), 2);
+
+        fulfiller.fulfill(3);
+        match promise.poll_once(wait_scope) {
+            Ok(result) => assert_eq!(result, Ok(3)),
+            Err(_) => panic!("promise should have resolved"),
+        }
+
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn poll_once_with_high_priority_perpetual_task() {
+    use std::rc::Rc;
+    use std::cell::Cell;
+    use gj::Priority;
+
+    fn tick(count: Rc<Cell<u32>>) -> Promise<(), ()> {
+        count.set(count.get() + 1);
+        Promise::yield_now().priority(Priority::High).then(move |()| tick(count))
+    }
+
+    EventLoop::top_level(|wait_scope| -> Result<(), ()> {
+        let ticks = Rc::new(Cell::new(0));
+        let ticks1 = ticks.clone();
+        Promise::ok(()).then(move |()| tick(ticks1)).detach(|()| ());
+
+        let (promise, fulfiller) = Promise::<u32, ()>::and_fulfiller();
+        let promise = match promise.poll_once(wait_scope) {
+            Ok(_) => panic!("promise should still be pending"),
+            Err(promise) => promise,
+        };
+        assert_eq!(ticks.get(), 1);
+
+        let promise = match promise.poll_once(wait_scope) {
+            Ok(_) => panic!("promise should still be pending"),
+            Err(promise) => promise,
+        };
+        assert_eq!(ticks.get(), 2);
+
+        fulfiller.fulfill(3);
+        match promise.poll_once(wait_scope) {
+            Ok(result) => assert_eq!(result, Ok(3)),
+            Err(_) => panic!("promise should have resolved"),
+        }
+
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn poll_once() {
+    EventLoop::top_level(|wait_scope| -> Result<(), ()> {
+        match Promise::<u32, ()>::ok(1).map(|x| Ok(x + 1)).poll_once(wait_scope) {
+            Ok(result) => assert_eq!(result, Ok(2)),
+            Err(_) => panic!("promise should have resolved"),
+        }
+
+        // The promise returned by the `then()` callback is only ready on the next pass.
+        let promise = Promise::<u32, ()>::ok(1).then(|x| Promise::ok(x + 1));
+        let promise = match promise.poll_once(wait_scope) {
+            Ok(_) => panic!("promise should still be pending"),
+            Err(promise) => promise,
+        };
+        match promise.poll_once(wait_scope) {
+            Ok(result) => assert_eq!(result, Ok(2)),
+            Err(_) => panic!("promise should have resolved"),
+        }
+
+        let (promise, fulfiller) = Promise::<u32, ()>::and_fulfiller();
+        let promise = match promise.map(|x| Ok(x * 10)).poll_once(wait_scope) {
+            Ok(_) => panic!("promise should still be pending"),
+            Err(promise) => promise,
+        };
+        let promise = match promise.poll_once(wait_scope) {
+            Ok(_) => panic!("promise should still be pending"),
+            Err(promise) => promise,
+        };
+        fulfiller.fulfill(4);
+        let value = try!(promise.then(|x| Promise::ok(x + 1)).wait(wait_scope,
+                                                                   &mut ClosedEventPort(())));
+        assert_eq!(value, 41);
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn then_spawn() {
+    use std::rc::Rc;
+    use std::cell::{Cell, RefCell};
+    use gj::PromiseExt;
+
+    EventLoop::top_level(|wait_scope| -> Result<(), ()> {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let log1 = log.clone();
+        let (spawned_done, spawned_fulfiller) = Promise::<(), ()>::and_fulfiller();
+        let failed = Rc::new(Cell::new(None));
+        let failed1 = failed.clone();
+
+        let promise = Promise::<u32, ()>::ok(7).then_spawn(move |&v| {
+            spawned_done.map_err(|()| 0u32).map(move |()| {
+                log1.borrow_mut().push(v);
+                Err(v)
+            })
+        }, move |e| failed1.set(Some(e)));
+
+        // The chain resolves without waiting for the spawned task.
+        assert_eq!(try!(promise.wait(wait_scope, &mut ClosedEventPort(()))), 7);
+        assert!(log.borrow().is_empty());
+
+        spawned_fulfiller.fulfill(());
+        try!(Promise::<(), ()>::yield_now().wait(wait_scope, &mut ClosedEventPort(())));
+        assert_eq!(*log.borrow(), vec![7]);
+        assert_eq!(failed.get(), Some(7));
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn catch_kind() {
+    use std::io::{Error, ErrorKind};
+
+    struct IoClosedEventPort;
+    impl gj::EventPort<Error> for IoClosedEventPort {
+        fn wait(&mut self) -> Result<(), Error> {
+            Err(Error::new(ErrorKind::Other, "closed"))
+        }
+    }
+
+    EventLoop::top_level(|wait_scope| -> Result<(), Error> {
+        let mut event_port = IoClosedEventPort;
+
+        let reset = Promise::<Vec<u8>, Error>::err(Error::new(ErrorKind::ConnectionReset, "reset"));
+        let caught = reset.catch_kind(ErrorKind::ConnectionReset, Vec::new);
+        assert_eq!(try!(caught.wait(wait_scope, &mut event_port)), Vec::<u8>::new());
+
+        let ok = Promise::<u32, Error>::ok(5).catch_kind(ErrorKind::ConnectionReset, || 0);
+        assert_eq!(try!(ok.wait(wait_scope, &mut event_port)), 5);
+
+        let other = Promise::<u32, Error>::err(Error::new(ErrorKind::BrokenPipe, "pipe"));
+        match other.catch_kind(ErrorKind::ConnectionReset, || 0).wait(wait_scope, &mut event_port) {
+            Err(e) => assert_eq!(e.kind(), ErrorKind::BrokenPipe),
+            Ok(_) => panic!("expected an error"),
+        }
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn deterministic_completion_order() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    let order = [5, 2, 7, 0, 3, 6, 1, 4];
+
+    let run_once = || -> Vec<usize> {
+        EventLoop::top_level(|wait_scope| -> Result<Vec<usize>, ()> {
+            let log = Rc::new(RefCell::new(Vec::new()));
+            let mut fulfillers = Vec::new();
+            let mut waiters = Vec::new();
+            for i in 0..order.len() {
+                let log = log.clone();
+                let (promise, fulfiller) = Promise::<(), ()>::and_fulfiller();
+                fulfillers.push(Some(fulfiller));
+                waiters.push(promise.map(move |()| {
+                    log.borrow_mut().push(i);
+                    Ok(())
+                }));
+            }
+            // All of the waiters are registered by now.
+            let waiters = Promise::all(waiters.into_iter());
+
+            // Fulfill in a scrambled order from within a running callback.
+            let driver = Promise::<(), ()>::yield_now().map(move |()| {
+                for &i in order.iter() {
+                    fulfillers[i].take().unwrap().fulfill(());
+                }
+                Ok(())
+            });
+
+            try!(waiters.join(driver).wait(wait_scope, &mut ClosedEventPort(())));
+            let result = log.borrow().clone();
+            Ok(result)
+        }).unwrap()
+    };
+
+    // Waiters complete in the order that their promises were fulfilled, which is the order in
+    // which their events were armed.
+    let first = run_once();
+    assert_eq!(first, order.to_vec());
+    for _ in 0..10 {
+        assert_eq!(run_once(), first);
+    }
+}
+
+#[test]
+fn map_clone() {
+    use std::rc::Rc;
+    use std::cell::Cell;
+    use gj::PromiseExt;
+
+    EventLoop::top_level(|wait_scope| -> Result<(), ()> {
+        let calls = Rc::new(Cell::new(0));
+        let calls1 = calls.clone();
+        let mut forked = Promise::<u32, ()>::ok(2).map_clone(move |x| {
+            calls1.set(calls1.get() + 1);
+            Ok(vec![x; 3])
+        });
+        let branches = vec![forked.add_branch(), forked.add_branch(), forked.add_branch()];
+        let values = try!(Promise::all(branches.into_iter()).wait(wait_scope,
+                                                                  &mut ClosedEventPort(())));
+        assert_eq!(values, vec![vec![2, 2, 2]; 3]);
+        assert_eq!(calls.get(), 1);
+        Ok(())
+    }).unwrap();
+}
+
+#[test]
+fn unconsumed_panic_resumed_on_drop() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    EventLoop::top_level(|wait_scope| -> Result<(), ()> {
+        let mut event_port = ClosedEventPort(());
+
+        let promise = Promise::<(), ()>::ok(()).map(|()| -> Result<(), ()> {
+            panic!("dropped")
+        }).eagerly_evaluate();
+        // Lets the eagerly evaluated callback run.
+        try!(Promise::<(), ()>::ok(()).wait(wait_scope, &mut event_port));
+        let payload = panic::catch_unwind(AssertUnwindSafe(move || drop(promise))).unwrap_err();
+        assert_eq!(payload.downcast_ref::<&'static str>(), Some(&"dropped"));
+
+        // The failing branch cancels the panicking one before `all()` gets to see its panic.
+        let promises = vec![Promise::ok(()).then(|()| -> Promise<(), ()> { panic!("cancelled") }),
+                            Promise::err(())];
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            Promise::all(promises.into_iter()).wait(wait_scope, &mut event_port)
+        }));
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&'static str>(), Some(&"cancelled"));
+
+        try!(Promise::<(), ()>::ok(()).wait(wait_scope, &mut event_port));
+        Ok(())
+    }).unwrap();
+}